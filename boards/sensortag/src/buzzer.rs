@@ -0,0 +1,257 @@
+//! Piezo buzzer
+//!
+//! Plays a queue of notes on the buzzer, driving it with the PWM output of a timer
+//! half at a 50% duty cycle. Each note is timed with a virtual alarm, so the queue
+//! plays asynchronously and the app can sleep until the completion callback.
+//!
+//! Note format
+//! -----------
+//!
+//! A queue holds up to `MAX_NOTES` notes of 4 bytes each, little endian:
+//!
+//! ```text
+//!  Offset  Size  Field
+//!  0       2     Frequency in Hz, 0 is a rest
+//!  2       2     Duration in ms
+//! ```
+//!
+//! Userspace interface
+//! -------------------
+//!
+//! * allow 0: Buffer holding the notes to play.
+//! * subscribe 0: Callback `(notes, 0, 0)`, called when the queue finished playing
+//!   with the number of notes played.
+//! * command 0: Check if the driver exists.
+//! * command 1: Play the first `data` notes of the allowed buffer.
+//! * command 2: Stop playing, without a callback.
+
+use core::cell::Cell;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use ticks;
+
+pub const DRIVER_NUM: usize = 0x90001;
+
+pub const MAX_NOTES: usize = 16;
+const NOTE_SIZE: usize = 4;
+
+pub static mut BUFFER: [u8; MAX_NOTES * NOTE_SIZE] = [0; MAX_NOTES * NOTE_SIZE];
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct Buzzer<'a, P: hil::pwm::Pwm + 'a, A: hil::time::Alarm + 'a> {
+    pwm: &'a P,
+    pin: &'a P::Pin,
+    alarm: &'a A,
+    notes: TakeCell<'static, [u8]>,
+    count: Cell<usize>,
+    next: Cell<usize>,
+    playing: Cell<bool>,
+    apps: Grant<App>,
+    current_app: Cell<Option<AppId>>,
+}
+
+impl<'a, P: hil::pwm::Pwm + 'a, A: hil::time::Alarm + 'a> Buzzer<'a, P, A> {
+    pub fn new(
+        pwm: &'a P,
+        pin: &'a P::Pin,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> Buzzer<'a, P, A> {
+        Buzzer {
+            pwm,
+            pin,
+            alarm,
+            notes: TakeCell::new(buffer),
+            count: Cell::new(0),
+            next: Cell::new(0),
+            playing: Cell::new(false),
+            apps: grant,
+            current_app: Cell::new(None),
+        }
+    }
+
+    /// Play a single tone, e.g. to signal an alert from within the kernel.
+    pub fn beep(&self, frequency_hz: u16, duration_ms: u16) -> ReturnCode {
+        if self.playing.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        self.notes.map(|notes| write_note(notes, 0, frequency_hz, duration_ms));
+        self.play(1)
+    }
+
+    pub fn stop(&self) {
+        if self.playing.get() {
+            self.alarm.disable();
+            self.pwm.stop(self.pin);
+            self.playing.set(false);
+            self.current_app.set(None);
+        }
+    }
+
+    fn play(&self, count: usize) -> ReturnCode {
+        self.count.set(count);
+        self.next.set(0);
+        self.playing.set(true);
+        self.play_next();
+        ReturnCode::SUCCESS
+    }
+
+    fn play_next(&self) {
+        let index = self.next.get();
+        if index >= self.count.get() {
+            self.finish();
+            return;
+        }
+        self.next.set(index + 1);
+
+        let (frequency_hz, duration_ms) = self.notes
+            .map(|notes| read_note(notes, index))
+            .unwrap_or((0, 0));
+
+        let res = if frequency_hz == 0 {
+            self.pwm.stop(self.pin);
+            ReturnCode::SUCCESS
+        } else {
+            let duty_cycle = self.pwm.get_maximum_duty_cycle() / 2;
+            self.pwm.start(self.pin, frequency_hz as usize, duty_cycle)
+        };
+        if res != ReturnCode::SUCCESS {
+            // Frequencies the timer can't generate are played as rests
+            self.pwm.stop(self.pin);
+        }
+
+        let ticks = ticks::ms_to_ticks::<A>(duration_ms as u32);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    fn finish(&self) {
+        self.pwm.stop(self.pin);
+        self.playing.set(false);
+
+        let played = self.count.get();
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| cb.schedule(played, 0, 0));
+            });
+        });
+    }
+
+    fn play_from_app(&self, appid: AppId, count: usize) -> ReturnCode {
+        if self.playing.get() {
+            return ReturnCode::EBUSY;
+        }
+        if count == 0 || count > MAX_NOTES {
+            return ReturnCode::EINVAL;
+        }
+
+        self.apps
+            .enter(appid, |app, _| {
+                let res = app.buffer
+                    .as_ref()
+                    .map_or(ReturnCode::ERESERVE, |buffer| {
+                        let len = count * NOTE_SIZE;
+                        if buffer.len() < len {
+                            return ReturnCode::EINVAL;
+                        }
+                        self.notes.map(|notes| {
+                            notes[..len].copy_from_slice(&buffer.as_ref()[..len]);
+                        });
+                        ReturnCode::SUCCESS
+                    });
+                if res != ReturnCode::SUCCESS {
+                    return res;
+                }
+
+                self.current_app.set(Some(appid));
+                self.play(count)
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<'a, P: hil::pwm::Pwm + 'a, A: hil::time::Alarm + 'a> hil::time::Client
+    for Buzzer<'a, P, A> {
+    fn fired(&self) {
+        if self.playing.get() {
+            self.play_next();
+        }
+    }
+}
+
+impl<'a, P: hil::pwm::Pwm + 'a, A: hil::time::Alarm + 'a> Driver for Buzzer<'a, P, A> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.play_from_app(appid, data),
+            2 => {
+                let owner = self.current_app
+                    .get()
+                    .map_or(false, |current| current.idx() == appid.idx());
+                if !owner {
+                    return ReturnCode::EBUSY;
+                }
+                self.stop();
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+fn read_note(notes: &[u8], index: usize) -> (u16, u16) {
+    let note = &notes[index * NOTE_SIZE..(index + 1) * NOTE_SIZE];
+    (
+        note[0] as u16 | (note[1] as u16) << 8,
+        note[2] as u16 | (note[3] as u16) << 8,
+    )
+}
+
+fn write_note(notes: &mut [u8], index: usize, frequency_hz: u16, duration_ms: u16) {
+    let note = &mut notes[index * NOTE_SIZE..(index + 1) * NOTE_SIZE];
+    note[0] = frequency_hz as u8;
+    note[1] = (frequency_hz >> 8) as u8;
+    note[2] = duration_ms as u8;
+    note[3] = (duration_ms >> 8) as u8;
+}
@@ -0,0 +1,224 @@
+//! Wall-clock time
+//!
+//! Keeps the UTC time on top of the AON RTC, which counts seconds through every
+//! sleep mode. Setting the time stores the offset between Unix time and the RTC,
+//! including the fraction of the second, so the clock costs nothing while the chip
+//! sleeps and keeps the sub-second phase it was set with. The time is lost on a
+//! power loss and has to be set again, until then it is unset.
+//!
+//! The time can be set by an app, or from within the kernel with `set_time`. The
+//! BLE radio driver only advertises and can't receive yet, so an app or kernel
+//! code with another link to a time source (e.g. the console) has to set it.
+//!
+//! Date format
+//! -----------
+//!
+//! Command 4 writes the UTC date and time to the allowed buffer, little endian:
+//!
+//! ```text
+//!  Offset  Size  Field
+//!  0       2     Year
+//!  2       1     Month, 1 to 12
+//!  3       1     Day of the month, 1 to 31
+//!  4       1     Hour, 0 to 23
+//!  5       1     Minute, 0 to 59
+//!  6       1     Second, 0 to 59
+//!  7       1     Day of the week, 0 (Sunday) to 6
+//!  8       2     Millisecond, 0 to 999
+//! ```
+//!
+//! Userspace interface
+//! -------------------
+//!
+//! * allow 0: Buffer receiving the date, at least 10 bytes.
+//! * command 0: Check if the driver exists.
+//! * command 1: Set the time to `data` seconds since the Unix epoch.
+//! * command 5: Set the time to `data` seconds and `data2` milliseconds (0 to
+//!   999) since the Unix epoch.
+//! * command 2: Get the seconds since the Unix epoch.
+//! * command 3: Get the milliseconds into the current second.
+//! * command 4: Write the current date and time to the allowed buffer.
+//!
+//! Commands 2 to 4 return EOFF while the time is unset.
+
+use cc26x0::rtc;
+use core::cell::Cell;
+use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
+
+pub const DRIVER_NUM: usize = 0x90002;
+
+pub const DATE_SIZE: usize = 10;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// The current RTC time, in 1/2^32 parts of a second.
+fn rtc_time() -> u64 {
+    let (sec, subsec) = unsafe { rtc::RTC.read_time() };
+    (sec as u64) << 32 | subsec as u64
+}
+
+/// A UTC date and time.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// 0 is Sunday
+    pub weekday: u8,
+    pub millisecond: u16,
+}
+
+/// The UTC date and time `epoch_seconds` plus `millisecond` after the Unix epoch.
+///
+/// Uses the days-to-civil algorithm for the proleptic Gregorian calendar, which
+/// works on 400 year eras so leap years need no special cases: `to_utc(0, 0)` is
+/// Thursday 1970-01-01 00:00:00 and `to_utc(951_782_400, 0)` is Tuesday 2000-02-29.
+pub fn to_utc(epoch_seconds: u32, millisecond: u16) -> DateTime {
+    let days = epoch_seconds / SECONDS_PER_DAY;
+    let seconds = epoch_seconds % SECONDS_PER_DAY;
+
+    // Shift the epoch to 0000-03-01, so leap days end the year
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    DateTime {
+        year: year as u16,
+        month: month as u8,
+        day: day as u8,
+        hour: (seconds / 3600) as u8,
+        minute: (seconds / 60 % 60) as u8,
+        second: (seconds % 60) as u8,
+        // 1970-01-01 was a Thursday
+        weekday: ((days + 4) % 7) as u8,
+        millisecond,
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct WallClock {
+    // Unix time at RTC time 0, in 1/2^32 parts of a second
+    offset: Cell<Option<u64>>,
+    apps: Grant<App>,
+}
+
+impl WallClock {
+    pub fn new(grant: Grant<App>) -> WallClock {
+        WallClock {
+            offset: Cell::new(None),
+            apps: grant,
+        }
+    }
+
+    /// Set the time to `epoch_seconds` plus `millisecond` since the Unix epoch.
+    pub fn set_time(&self, epoch_seconds: u32, millisecond: u16) {
+        // Rounded up, so the time reads back as the same millisecond
+        let subsec = (((millisecond as u64) << 32) + 999) / 1000;
+        let time = (epoch_seconds as u64) << 32 | subsec;
+        self.offset.set(Some(time.wrapping_sub(rtc_time())));
+    }
+
+    /// Seconds since the Unix epoch and milliseconds into the second.
+    pub fn now(&self) -> Option<(u32, u16)> {
+        self.offset.get().map(|offset| {
+            let time = offset.wrapping_add(rtc_time());
+            let millisecond = (((time & 0xFFFF_FFFF) * 1000) >> 32) as u16;
+            ((time >> 32) as u32, millisecond)
+        })
+    }
+
+    pub fn utc(&self) -> Option<DateTime> {
+        self.now()
+            .map(|(seconds, millisecond)| to_utc(seconds, millisecond))
+    }
+
+    fn write_date(&self, appid: AppId) -> ReturnCode {
+        let date = match self.utc() {
+            Some(date) => date,
+            None => return ReturnCode::EOFF,
+        };
+
+        self.apps
+            .enter(appid, |app, _| {
+                app.buffer.as_mut().map_or(ReturnCode::ERESERVE, |buffer| {
+                    if buffer.len() < DATE_SIZE {
+                        return ReturnCode::ESIZE;
+                    }
+                    let buffer = buffer.as_mut();
+                    buffer[0] = date.year as u8;
+                    buffer[1] = (date.year >> 8) as u8;
+                    buffer[2] = date.month;
+                    buffer[3] = date.day;
+                    buffer[4] = date.hour;
+                    buffer[5] = date.minute;
+                    buffer[6] = date.second;
+                    buffer[7] = date.weekday;
+                    buffer[8] = date.millisecond as u8;
+                    buffer[9] = (date.millisecond >> 8) as u8;
+                    ReturnCode::SUCCESS
+                })
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl Driver for WallClock {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => {
+                self.set_time(data as u32, 0);
+                ReturnCode::SUCCESS
+            }
+            2 => self.now().map_or(ReturnCode::EOFF, |(seconds, _)| {
+                ReturnCode::SuccessWithValue {
+                    value: seconds as usize,
+                }
+            }),
+            3 => self.now().map_or(ReturnCode::EOFF, |(_, millisecond)| {
+                ReturnCode::SuccessWithValue {
+                    value: millisecond as usize,
+                }
+            }),
+            4 => self.write_date(appid),
+            5 => {
+                if data2 >= 1000 {
+                    return ReturnCode::EINVAL;
+                }
+                self.set_time(data as u32, data2 as u16);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
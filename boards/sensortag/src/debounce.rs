@@ -0,0 +1,180 @@
+//! Debounced GPIO input
+//!
+//! Wraps an input pin so that contact bounce never reaches its client. Every edge
+//! on the pin (re)starts a virtual alarm, and only once the pin has been quiet for
+//! the settle time is its level sampled. A level different from the last stable
+//! one is a single clean edge, which is delivered if it matches the interrupt mode
+//! the client asked for. `read` returns the stable level.
+//!
+//! `DebouncedPin` implements the GPIO HIL itself, so it drops in for the raw pin
+//! of capsules like `capsules::button`. A `LevelClient` additionally sees every
+//! stable level change, whether or not the regular client has interrupts enabled.
+//!
+//! The settle time delays every event by that long, 20ms is enough for the
+//! SensorTag buttons without feeling sluggish.
+
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::gpio::InterruptMode;
+use ticks;
+
+pub trait LevelClient {
+    /// The pin registered with `identifier` settled at `high`.
+    fn level_changed(&self, identifier: usize, high: bool);
+}
+
+pub struct DebouncedPin<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> {
+    pin: &'a P,
+    alarm: &'a A,
+    settle_ms: u32,
+    level: Cell<bool>,
+    watching: Cell<bool>,
+    client: Cell<Option<&'a hil::gpio::Client>>,
+    client_data: Cell<usize>,
+    // Edges the client has interrupts enabled for
+    rising: Cell<bool>,
+    falling: Cell<bool>,
+    level_client: Cell<Option<(&'a LevelClient, usize)>>,
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> DebouncedPin<'a, P, A> {
+    pub fn new(pin: &'a P, alarm: &'a A, settle_ms: u32) -> DebouncedPin<'a, P, A> {
+        DebouncedPin {
+            pin,
+            alarm,
+            settle_ms,
+            level: Cell::new(false),
+            watching: Cell::new(false),
+            client: Cell::new(None),
+            client_data: Cell::new(0),
+            rising: Cell::new(false),
+            falling: Cell::new(false),
+            level_client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a hil::gpio::Client) {
+        self.client.set(Some(client));
+    }
+
+    /// Report every stable level change to `client`, as `identifier`.
+    pub fn set_level_client(&self, client: &'a LevelClient, identifier: usize) {
+        self.level_client.set(Some((client, identifier)));
+        self.update_interrupt();
+    }
+
+    /// Watch the raw pin while anybody listens for edges.
+    fn update_interrupt(&self) {
+        let client_listens = self.rising.get() || self.falling.get();
+        let watch = client_listens || self.level_client.get().is_some();
+        if watch == self.watching.get() {
+            return;
+        }
+        self.watching.set(watch);
+
+        if watch {
+            self.level.set(self.pin.read());
+            self.pin.enable_interrupt(0, InterruptMode::EitherEdge);
+        } else {
+            self.alarm.disable();
+            self.pin.disable_interrupt();
+        }
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> hil::gpio::Pin
+    for DebouncedPin<'a, P, A> {
+    fn make_output(&self) {
+        self.pin.make_output();
+    }
+
+    fn make_input(&self) {
+        self.pin.make_input();
+        self.level.set(self.pin.read());
+    }
+
+    fn disable(&self) {
+        self.pin.disable();
+    }
+
+    fn set(&self) {
+        self.pin.set();
+    }
+
+    fn clear(&self) {
+        self.pin.clear();
+    }
+
+    fn toggle(&self) {
+        self.pin.toggle();
+    }
+
+    fn read(&self) -> bool {
+        if self.watching.get() {
+            self.level.get()
+        } else {
+            // Nothing tracks the level while no one listens for edges
+            self.pin.read()
+        }
+    }
+
+    fn enable_interrupt(&self, client_data: usize, mode: InterruptMode) {
+        self.client_data.set(client_data);
+        let (rising, falling) = match mode {
+            InterruptMode::RisingEdge => (true, false),
+            InterruptMode::FallingEdge => (false, true),
+            InterruptMode::EitherEdge => (true, true),
+        };
+        self.rising.set(rising);
+        self.falling.set(falling);
+        self.update_interrupt();
+    }
+
+    fn disable_interrupt(&self) {
+        self.rising.set(false);
+        self.falling.set(false);
+        self.update_interrupt();
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + hil::gpio::PinCtl + 'a, A: hil::time::Alarm + 'a>
+    hil::gpio::PinCtl for DebouncedPin<'a, P, A> {
+    fn set_input_mode(&self, mode: hil::gpio::InputMode) {
+        self.pin.set_input_mode(mode);
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> hil::gpio::Client
+    for DebouncedPin<'a, P, A> {
+    fn fired(&self, _: usize) {
+        // Still bouncing, wait for the pin to be quiet for the whole settle time
+        let ticks = ticks::ms_to_ticks::<A>(self.settle_ms);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> hil::time::Client
+    for DebouncedPin<'a, P, A> {
+    fn fired(&self) {
+        let high = self.pin.read();
+        if high == self.level.get() {
+            // Bounced back to where it was
+            return;
+        }
+        self.level.set(high);
+
+        let deliver = if high {
+            self.rising.get()
+        } else {
+            self.falling.get()
+        };
+        if deliver {
+            self.client
+                .get()
+                .map(|client| client.fired(self.client_data.get()));
+        }
+        self.level_client
+            .get()
+            .map(|(client, identifier)| client.level_changed(identifier, high));
+    }
+}
@@ -0,0 +1,223 @@
+//! Button gestures
+//!
+//! Turns the presses of debounced buttons into gestures:
+//!
+//! * Short press: pressed and released, and not pressed again within
+//!   `DOUBLE_CLICK_MS`. Reported once that window has passed.
+//! * Double click: pressed again within `DOUBLE_CLICK_MS` of a short press,
+//!   reported on the second release.
+//! * Long press: held for `LONG_PRESS_MS`, reported while still held. The release
+//!   that ends it is not reported.
+//!
+//! Every button is tracked on its own, the timeouts of all of them share one
+//! virtual alarm. The gestures are reported alongside `capsules::button`, which
+//! keeps reporting the raw presses and releases of the same buttons.
+//!
+//! Userspace interface
+//! -------------------
+//!
+//! * subscribe 0: Callback `(button, gesture, 0)` for every gesture, with gesture 0
+//!   for a short press, 1 for a long press and 2 for a double click.
+//! * command 0: Get the number of buttons.
+
+use capsules::button::GpioMode;
+use core::cell::Cell;
+use debounce::{DebouncedPin, LevelClient};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil;
+use ticks;
+
+pub const DRIVER_NUM: usize = 0x90003;
+
+pub const MAX_BUTTONS: usize = 4;
+
+const LONG_PRESS_MS: u32 = 800;
+const DOUBLE_CLICK_MS: u32 = 300;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Gesture {
+    ShortPress = 0,
+    LongPress = 1,
+    DoubleClick = 2,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Phase {
+    Idle,
+    // Held until the deadline, `second` for the second press of a double click
+    Pressed { second: bool },
+    // Released after a short press, waiting for a second press until the deadline
+    Released,
+    // Held past the long press, waiting for the release
+    Held,
+}
+
+struct Button {
+    phase: Cell<Phase>,
+    deadline: Cell<u32>,
+}
+
+impl Button {
+    fn new() -> Button {
+        Button {
+            phase: Cell::new(Phase::Idle),
+            deadline: Cell::new(0),
+        }
+    }
+
+    fn is_timing(&self) -> bool {
+        match self.phase.get() {
+            Phase::Pressed { .. } | Phase::Released => true,
+            Phase::Idle | Phase::Held => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct ButtonGestures<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> {
+    pins: &'a [(&'a DebouncedPin<'a, P, A>, GpioMode)],
+    alarm: &'a A,
+    buttons: [Button; MAX_BUTTONS],
+    apps: Grant<App>,
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> ButtonGestures<'a, P, A> {
+    pub fn new(
+        pins: &'a [(&'a DebouncedPin<'a, P, A>, GpioMode)],
+        alarm: &'a A,
+        grant: Grant<App>,
+    ) -> ButtonGestures<'a, P, A> {
+        if pins.len() > MAX_BUTTONS {
+            panic!("Gestures support at most {} buttons", MAX_BUTTONS);
+        }
+
+        ButtonGestures {
+            pins,
+            alarm,
+            buttons: [Button::new(), Button::new(), Button::new(), Button::new()],
+            apps: grant,
+        }
+    }
+
+    fn report(&self, button: usize, gesture: Gesture) {
+        self.apps.each(|app| {
+            app.callback
+                .map(|mut cb| cb.schedule(button, gesture as usize, 0));
+        });
+    }
+
+    /// Set the alarm for the earliest deadline of any button.
+    fn schedule(&self) {
+        let now = self.alarm.now();
+        let next = self.buttons[..self.pins.len()]
+            .iter()
+            .filter(|button| button.is_timing())
+            .map(|button| button.deadline.get())
+            .min_by_key(|deadline| {
+                let remaining = deadline.wrapping_sub(now);
+                // Deadlines already passed wrap to huge values
+                if remaining > (1 << 31) {
+                    0
+                } else {
+                    remaining
+                }
+            });
+
+        match next {
+            Some(deadline) => self.alarm.set_alarm(deadline),
+            None => self.alarm.disable(),
+        }
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> LevelClient
+    for ButtonGestures<'a, P, A> {
+    fn level_changed(&self, index: usize, high: bool) {
+        let pressed = match self.pins[index].1 {
+            GpioMode::LowWhenPressed => !high,
+            GpioMode::HighWhenPressed => high,
+        };
+        let button = &self.buttons[index];
+        let now = self.alarm.now();
+
+        if pressed {
+            let second = button.phase.get() == Phase::Released;
+            button.phase.set(Phase::Pressed { second });
+            button
+                .deadline
+                .set(now.wrapping_add(ticks::ms_to_ticks::<A>(LONG_PRESS_MS)));
+        } else {
+            match button.phase.get() {
+                Phase::Pressed { second: true } => {
+                    button.phase.set(Phase::Idle);
+                    self.report(index, Gesture::DoubleClick);
+                }
+                Phase::Pressed { second: false } => {
+                    button.phase.set(Phase::Released);
+                    button
+                        .deadline
+                        .set(now.wrapping_add(ticks::ms_to_ticks::<A>(DOUBLE_CLICK_MS)));
+                }
+                Phase::Idle | Phase::Released | Phase::Held => {
+                    button.phase.set(Phase::Idle);
+                }
+            }
+        }
+
+        self.schedule();
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> hil::time::Client
+    for ButtonGestures<'a, P, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        for (index, button) in self.buttons[..self.pins.len()].iter().enumerate() {
+            if !button.is_timing() || now.wrapping_sub(button.deadline.get()) > (1 << 31) {
+                continue;
+            }
+
+            if button.phase.get() == Phase::Released {
+                button.phase.set(Phase::Idle);
+                self.report(index, Gesture::ShortPress);
+            } else {
+                button.phase.set(Phase::Held);
+                self.report(index, Gesture::LongPress);
+            }
+        }
+
+        self.schedule();
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a, A: hil::time::Alarm + 'a> Driver for ButtonGestures<'a, P, A> {
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, _: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.pins.len(),
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! GPIO port
+//!
+//! Drives a group of pins with a single register write, so all of them change at
+//! the same instant. Apps use it to bit-bang parallel buses or to switch several
+//! LEDs without the intermediate states that separate `capsules::gpio` calls
+//! would show. Pins are addressed by a mask with bit n for DIO n.
+//!
+//! Only the pins handed to userspace GPIO can be used, the mask of them is
+//! returned by command 0. Commands with other pins in their mask fail with
+//! EINVAL, without changing any pin.
+//!
+//! Userspace interface
+//! -------------------
+//!
+//! * command 0: Get the mask of the pins that can be used.
+//! * command 1: Make the pins in `data` outputs.
+//! * command 2: Make the pins in `data` inputs, with `data2` selecting the
+//!   resistor: 0 pull-up, 1 pull-down, 2 none.
+//! * command 3: Drive the pins in `data` high.
+//! * command 4: Drive the pins in `data` low.
+//! * command 5: Invert the pins in `data`.
+//! * command 6: Get the levels of all usable pins.
+
+use cc26x0::gpio;
+use kernel::{AppId, Driver, ReturnCode};
+use kernel::hil::gpio::{InputMode, Pin, PinCtl};
+
+pub const DRIVER_NUM: usize = 0x90005;
+
+fn input_mode(config: usize) -> InputMode {
+    match config {
+        0 => InputMode::PullUp,
+        1 => InputMode::PullDown,
+        _ => InputMode::PullNone,
+    }
+}
+
+pub struct GpioPort {
+    port: &'static gpio::Port,
+    // The pins apps may use
+    mask: u32,
+}
+
+impl GpioPort {
+    pub fn new(port: &'static gpio::Port, mask: u32) -> GpioPort {
+        GpioPort { port, mask }
+    }
+
+    fn for_each_pin<F: Fn(&gpio::GPIOPin)>(&self, mask: u32, f: F) {
+        let mut pending = mask;
+        while pending != 0 {
+            let pin = pending.trailing_zeros() as usize;
+            pending &= !(1 << pin);
+            f(&self.port[pin]);
+        }
+    }
+}
+
+impl Driver for GpioPort {
+    fn command(&self, command_num: usize, data: usize, data2: usize, _: AppId) -> ReturnCode {
+        let pins = data as u32;
+        if command_num != 0 && command_num != 6 && pins & !self.mask != 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.mask as usize,
+            },
+            1 => {
+                self.for_each_pin(pins, |pin| pin.make_output());
+                ReturnCode::SUCCESS
+            }
+            2 => {
+                if data2 > 2 {
+                    return ReturnCode::EINVAL;
+                }
+                self.for_each_pin(pins, |pin| {
+                    pin.make_input();
+                    pin.set_input_mode(input_mode(data2));
+                });
+                ReturnCode::SUCCESS
+            }
+            3 => {
+                self.port.set_pins(pins);
+                ReturnCode::SUCCESS
+            }
+            4 => {
+                self.port.clear_pins(pins);
+                ReturnCode::SUCCESS
+            }
+            5 => {
+                self.port.toggle_pins(pins);
+                ReturnCode::SUCCESS
+            }
+            6 => ReturnCode::SuccessWithValue {
+                value: (self.port.read_pins() & self.mask) as usize,
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
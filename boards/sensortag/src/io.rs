@@ -3,6 +3,7 @@ use kernel::hil::uart::{self, UART};
 use kernel::hil::gpio::Pin;
 use cc26xx;
 use cc26x0;
+use pinmap::PINS;
 
 pub struct Writer {
     initialized: bool,
@@ -63,8 +64,8 @@ pub unsafe extern "C" fn rust_begin_unwind(
     let _ = write(writer, _args);
     let _ = writer.write_str("\"\r\n");
 
-    let led0 = &cc26xx::gpio::PORT[10]; // Red led
-    let led1 = &cc26xx::gpio::PORT[15]; // Green led
+    let led0 = &cc26xx::gpio::PORT[PINS.led_red];
+    let led1 = &cc26xx::gpio::PORT[PINS.led_green];
 
     led0.make_output();
     led1.make_output();
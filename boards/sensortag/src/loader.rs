@@ -0,0 +1,328 @@
+//! Serial process loader
+//!
+//! Holding button 1 while the board resets starts the loader instead of the
+//! kernel. The loader speaks the serial protocol of the Tock bootloader on UART0
+//! (115200 baud), which lets tockloader list, install, erase and verify apps
+//! without a JTAG probe, e.g.
+//!
+//! ```text
+//! tockloader install --app-address 0x10000 --port /dev/ttyACM0 blink.tab
+//! ```
+//!
+//! Messages from the host are the command arguments followed by the escape
+//! character 0xFC and the command byte, any 0xFC in the arguments is sent twice.
+//! Responses start with 0xFC and the response code, followed by the response data
+//! in which 0xFC is doubled as well.
+//!
+//! Only the application region from `layout.ld` can be written or erased. Pages
+//! are `PAGE_SIZE` bytes, writing a page which isn't erased rewrites the whole
+//! flash sector around it. The exit command resets the chip, which then boots
+//! into the kernel and loads the new apps.
+
+use bytes::{read_u32, write_u32};
+use cc26x0::{delay, flash, gpio, uart};
+use core::ptr;
+use kernel::common::VolatileCell;
+use kernel::hil::gpio::{InputMode, Pin, PinCtl};
+use kernel::hil::uart::{self as hil_uart, UART};
+use kernel::ReturnCode;
+
+const ESCAPE_CHAR: u8 = 0xFC;
+
+const COMMAND_PING: u8 = 0x01;
+const COMMAND_INFO: u8 = 0x03;
+const COMMAND_RESET: u8 = 0x05;
+const COMMAND_ERASE_PAGE: u8 = 0x06;
+const COMMAND_WRITE_PAGE: u8 = 0x07;
+const COMMAND_READ_RANGE: u8 = 0x11;
+const COMMAND_GET_ATTRIBUTE: u8 = 0x14;
+const COMMAND_CRC_INTERNAL_FLASH: u8 = 0x15;
+const COMMAND_EXIT: u8 = 0x22;
+
+const RESPONSE_OVERFLOW: u8 = 0x10;
+const RESPONSE_PONG: u8 = 0x11;
+const RESPONSE_BADADDR: u8 = 0x12;
+const RESPONSE_INTERROR: u8 = 0x13;
+const RESPONSE_BADARGS: u8 = 0x14;
+const RESPONSE_OK: u8 = 0x15;
+const RESPONSE_UNKNOWN: u8 = 0x16;
+const RESPONSE_READ_RANGE: u8 = 0x20;
+const RESPONSE_GET_ATTRIBUTE: u8 = 0x22;
+const RESPONSE_CRC_INTERNAL_FLASH: u8 = 0x23;
+const RESPONSE_INFO: u8 = 0x25;
+
+pub const PAGE_SIZE: usize = 512;
+// Address followed by a page of data
+const MAX_MESSAGE_SIZE: usize = 4 + PAGE_SIZE;
+
+const INFO: &'static str = "{\"version\":\"1.0.0\",\"name\":\"sensortag serial loader\"}";
+const INFO_SIZE: usize = 192;
+
+const NUM_ATTRIBUTES: usize = 16;
+const ATTRIBUTE_SIZE: usize = 64;
+const ATTRIBUTE_KEY_SIZE: usize = 8;
+const ATTRIBUTES: [(&'static str, &'static str); 3] = [
+    ("board", "sensortag"),
+    ("arch", "cortex-m3"),
+    ("pagesize", "512"),
+];
+
+const SCB_AIRCR: usize = 0xE000_ED0C;
+const AIRCR_SYSRESETREQ: u32 = 0x05FA_0004;
+
+/// Whether the (active low) button is held down.
+pub fn button_held(pin: &gpio::GPIOPin) -> bool {
+    pin.make_input();
+    pin.set_input_mode(InputMode::PullUp);
+
+    // Let the pull-up charge the pin
+    delay::busy_wait_ms(1);
+
+    !pin.read()
+}
+
+struct Loader<'a> {
+    uart: &'a uart::UART,
+    flash: &'a flash::Flash,
+    sector: &'a mut [u8],
+}
+
+/// Serve loader commands until the host asks to exit, then reset the chip.
+/// `memory` is used as scratch space for a flash sector and a message.
+pub fn run(uart: &uart::UART, flash: &flash::Flash, memory: &mut [u8]) -> ! {
+    uart.init(hil_uart::UARTParams {
+        baud_rate: 115200,
+        stop_bits: hil_uart::StopBits::One,
+        parity: hil_uart::Parity::None,
+        hw_flow_control: false,
+    });
+
+    let (sector, rest) = memory.split_at_mut(flash::SECTOR_SIZE);
+    let message = &mut rest[..MAX_MESSAGE_SIZE];
+    let mut loader = Loader {
+        uart,
+        flash,
+        sector,
+    };
+
+    let mut len = 0;
+    let mut overflow = false;
+    loop {
+        let mut byte = uart.read_byte();
+        if byte == ESCAPE_CHAR {
+            byte = uart.read_byte();
+            if byte != ESCAPE_CHAR {
+                if overflow {
+                    loader.respond(RESPONSE_OVERFLOW, &[]);
+                } else {
+                    loader.command(byte, &message[..len]);
+                }
+                len = 0;
+                overflow = false;
+                continue;
+            }
+        }
+
+        if len < message.len() {
+            message[len] = byte;
+            len += 1;
+        } else {
+            overflow = true;
+        }
+    }
+}
+
+impl<'a> Loader<'a> {
+    fn command(&mut self, command: u8, args: &[u8]) {
+        match command {
+            COMMAND_PING => self.respond(RESPONSE_PONG, &[]),
+            COMMAND_INFO => {
+                let mut info = [0; INFO_SIZE + 1];
+                info[0] = INFO.len() as u8;
+                info[1..INFO.len() + 1].copy_from_slice(INFO.as_bytes());
+                self.respond(RESPONSE_INFO, &info);
+            }
+            // Sent to synchronize, the message buffer has already been cleared
+            COMMAND_RESET => (),
+            COMMAND_ERASE_PAGE if args.len() == 4 => {
+                let response = self.write_page(read_u32(&args[0..4]) as usize, None);
+                self.respond(response, &[]);
+            }
+            COMMAND_WRITE_PAGE if args.len() == 4 + PAGE_SIZE => {
+                let response = self.write_page(read_u32(&args[0..4]) as usize, Some(&args[4..]));
+                self.respond(response, &[]);
+            }
+            COMMAND_READ_RANGE if args.len() == 6 => {
+                let address = read_u32(&args[0..4]) as usize;
+                let length = (args[4] as usize) | (args[5] as usize) << 8;
+                if address.checked_add(length).map_or(true, |end| end > flash::FLASH_SIZE) {
+                    self.respond(RESPONSE_BADADDR, &[]);
+                } else {
+                    self.respond_header(RESPONSE_READ_RANGE);
+                    for i in 0..length {
+                        self.send_escaped(read_flash(address + i));
+                    }
+                }
+            }
+            COMMAND_GET_ATTRIBUTE if args.len() == 1 => {
+                let index = args[0] as usize;
+                if index >= NUM_ATTRIBUTES {
+                    self.respond(RESPONSE_BADARGS, &[]);
+                } else {
+                    let mut attribute = [0; ATTRIBUTE_SIZE];
+                    self.encode_attribute(index, &mut attribute);
+                    self.respond(RESPONSE_GET_ATTRIBUTE, &attribute);
+                }
+            }
+            COMMAND_CRC_INTERNAL_FLASH if args.len() == 8 => {
+                let address = read_u32(&args[0..4]) as usize;
+                let length = read_u32(&args[4..8]) as usize;
+                if address.checked_add(length).map_or(true, |end| end > flash::FLASH_SIZE) {
+                    self.respond(RESPONSE_BADADDR, &[]);
+                } else {
+                    let crc = crc32((address..address + length).map(read_flash));
+                    let mut response = [0; 4];
+                    write_u32(&mut response, crc);
+                    self.respond(RESPONSE_CRC_INTERNAL_FLASH, &response);
+                }
+            }
+            COMMAND_EXIT => {
+                self.respond(RESPONSE_OK, &[]);
+                reset();
+            }
+            COMMAND_ERASE_PAGE
+            | COMMAND_WRITE_PAGE
+            | COMMAND_READ_RANGE
+            | COMMAND_GET_ATTRIBUTE
+            | COMMAND_CRC_INTERNAL_FLASH => self.respond(RESPONSE_BADARGS, &[]),
+            _ => self.respond(RESPONSE_UNKNOWN, &[]),
+        }
+    }
+
+    /// Write a page, or erase it when there is no data.
+    fn write_page(&mut self, address: usize, data: Option<&[u8]>) -> u8 {
+        let (app_start, app_len) = self.flash.app_region();
+        if address % PAGE_SIZE != 0 || address < app_start
+            || address
+                .checked_add(PAGE_SIZE)
+                .map_or(true, |end| end > app_start + app_len)
+        {
+            return RESPONSE_BADADDR;
+        }
+
+        let erased = [0xFF; PAGE_SIZE];
+        let data = data.unwrap_or(&erased);
+        let current = (address..address + PAGE_SIZE).map(read_flash);
+
+        // Programming can only clear bits, anything else needs the sector erased
+        let needs_erase = current.zip(data.iter()).any(|(c, &d)| c & d != d);
+        let res = if !needs_erase {
+            self.flash.program(address, data)
+        } else {
+            let sector = address / flash::SECTOR_SIZE;
+            let start = sector * flash::SECTOR_SIZE;
+            let offset = address - start;
+
+            for (i, byte) in self.sector.iter_mut().enumerate() {
+                *byte = read_flash(start + i);
+            }
+            self.sector[offset..offset + PAGE_SIZE].copy_from_slice(data);
+
+            match self.flash.erase(sector) {
+                ReturnCode::SUCCESS => self.flash.program(start, &self.sector[..]),
+                res => res,
+            }
+        };
+
+        match res {
+            ReturnCode::SUCCESS => RESPONSE_OK,
+            ReturnCode::EINVAL => RESPONSE_BADADDR,
+            _ => RESPONSE_INTERROR,
+        }
+    }
+
+    /// Attributes are an 8 byte key padded with zeros, the length of the value
+    /// and the value. Unused attributes are all zeros.
+    fn encode_attribute(&self, index: usize, attribute: &mut [u8]) {
+        let mut appaddr = [0; 10];
+        let (key, value) = match index {
+            i if i < ATTRIBUTES.len() => {
+                let (key, value) = ATTRIBUTES[i];
+                (key.as_bytes(), value.as_bytes())
+            }
+            i if i == ATTRIBUTES.len() => {
+                let (app_start, _) = self.flash.app_region();
+                ("appaddr".as_bytes(), format_hex(app_start as u32, &mut appaddr))
+            }
+            _ => return,
+        };
+
+        attribute[..key.len()].copy_from_slice(key);
+        attribute[ATTRIBUTE_KEY_SIZE] = value.len() as u8;
+        attribute[ATTRIBUTE_KEY_SIZE + 1..ATTRIBUTE_KEY_SIZE + 1 + value.len()]
+            .copy_from_slice(value);
+    }
+
+    fn respond_header(&self, response: u8) {
+        self.uart.send_byte(ESCAPE_CHAR);
+        self.uart.send_byte(response);
+    }
+
+    fn respond(&self, response: u8, data: &[u8]) {
+        self.respond_header(response);
+        for &byte in data {
+            self.send_escaped(byte);
+        }
+    }
+
+    fn send_escaped(&self, byte: u8) {
+        if byte == ESCAPE_CHAR {
+            self.uart.send_byte(ESCAPE_CHAR);
+        }
+        self.uart.send_byte(byte);
+    }
+}
+
+fn read_flash(address: usize) -> u8 {
+    unsafe { ptr::read_volatile(address as *const u8) }
+}
+
+/// Format as "0x" followed by the hex digits, without leading zeros.
+fn format_hex(value: u32, buf: &mut [u8; 10]) -> &[u8] {
+    const DIGITS: &'static [u8; 16] = b"0123456789abcdef";
+
+    buf[0] = b'0';
+    buf[1] = b'x';
+    let digits = 8 - (value.leading_zeros() as usize / 4);
+    let digits = if digits == 0 { 1 } else { digits };
+    for i in 0..digits {
+        let shift = 4 * (digits - 1 - i);
+        buf[2 + i] = DIGITS[((value >> shift) & 0xF) as usize];
+    }
+    &buf[..2 + digits]
+}
+
+/// CRC-32 as used by tockloader (reflected, polynomial 0x04C11DB7)
+fn crc32<I: Iterator<Item = u8>>(data: I) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn reset() -> ! {
+    // Give the UART time to send the last response, a full FIFO takes ~3ms
+    delay::busy_wait_ms(5);
+
+    let aircr = unsafe { &*(SCB_AIRCR as *const VolatileCell<u32>) };
+    aircr.set(AIRCR_SYSRESETREQ);
+    loop {}
+}
@@ -0,0 +1,551 @@
+//! Persistent sensor data logger
+//!
+//! Stores timestamped sensor records in the kernel region of the external flash.
+//! The region is treated as a ring of fixed size record slots: the record with
+//! sequence number `n` is always stored in slot `n % capacity`.
+//!
+//! The log is append-only. Records are programmed into erased flash, without
+//! touching the records around them, and a sector is erased when the log moves
+//! into it, taking the oldest records of the ring with it. Each sector is
+//! therefore erased once per pass around the ring, and an append torn by a reset
+//! or power loss can only damage its own slot.
+//!
+//! Every record carries a CRC, which makes torn records (and slots never written)
+//! detectable. On boot the whole region is read through the nonvolatile storage
+//! driver and the log continues after the newest valid record. A slot after it
+//! that isn't erased any more, left by a torn append, is skipped.
+//!
+//! Record format
+//! -------------
+//!
+//! Records are `RECORD_SIZE` (32) bytes, multi-byte fields are little endian:
+//!
+//! ```text
+//!  Offset  Size  Field
+//!  0       1     Magic, always 0xA5
+//!  1       1     Record kind, chosen by the producer (sensor id)
+//!  2       1     Payload length (0 to 16)
+//!  3       1     Reserved, 0xFF
+//!  4       4     Sequence number
+//!  8       4     Timestamp, RTC seconds
+//!  12      2     Timestamp, fraction of the second in 1/65536 s
+//!  14      16    Payload, unused bytes are 0xFF
+//!  30      2     CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF) over bytes 0 to 29
+//! ```
+//!
+//! Dump format
+//! -----------
+//!
+//! A dump is written to the UART console as raw binary data, oldest record first.
+//! It is sent one buffer at a time through the logger's own device on the shared
+//! console UART (see `uart_mux`), which the logger holds for the whole dump so no
+//! console output ends up in the middle of it:
+//!
+//! ```text
+//!  Offset  Size  Field
+//!  0       4     Magic, "TLOG"
+//!  4       1     Format version, 1
+//!  5       1     Record size, 32
+//!  6       2     Reserved, 0
+//!  8       4     Number of records that follow (N)
+//!  12      32*N  Records, in the format above
+//! ```
+//!
+//! Records are dumped exactly as stored. A reader must discard records with a
+//! bad magic or CRC, and records whose sequence number is not the one expected
+//! at that position (left over from an earlier pass around the ring).
+//!
+//! Userspace interface
+//! -------------------
+//!
+//! * allow 0: Buffer holding the payload of the next record.
+//! * subscribe 0: Callback `(event, value, 0)`, where event 0 is an append
+//!   completing with `value` as the return code, and event 1 is a dump completing
+//!   with `value` as the number of records dumped.
+//! * command 0: Check if the driver exists.
+//! * command 1: Append a record of kind `data` with the first (at most 16)
+//!   bytes of the allowed buffer as its payload.
+//! * command 2: Dump the log over the UART console.
+//! * command 3: Get the number of records in the log.
+
+use bytes::{read_u32, write_u32};
+use cc26x0::{ext_flash, rtc};
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use kernel::hil::uart::UART;
+use uart_mux;
+
+pub const DRIVER_NUM: usize = 0x90000;
+
+pub const RECORD_SIZE: usize = 32;
+pub const MAX_PAYLOAD: usize = 16;
+
+const RECORDS_PER_SECTOR: usize = ext_flash::SECTOR_SIZE / RECORD_SIZE;
+
+const RECORD_MAGIC: u8 = 0xA5;
+const HEADER_SIZE: usize = 14;
+const CRC_OFFSET: usize = 30;
+
+const DUMP_MAGIC: &'static [u8; 4] = b"TLOG";
+const DUMP_VERSION: u8 = 1;
+
+/// Number of bytes read from the flash at a time while scanning or dumping.
+pub const BUFFER_SIZE: usize = 8 * RECORD_SIZE;
+pub static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Uninitialized,
+    Scanning { slot: usize },
+    // Making sure the slot of the next record is erased
+    Checking,
+    Idle,
+    Erasing,
+    Appending,
+    Dumping { seq: u32, last: u32 },
+    // Sending the header or the records before `seq` to the UART
+    Sending { seq: u32, last: u32 },
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct Logger<'a> {
+    storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+    flash: &'a ext_flash::ExtFlash,
+    uart: &'a uart_mux::UartDevice<'a>,
+    start_address: usize,
+    capacity: usize,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    newest: Cell<Option<u32>>,
+    next_seq: Cell<u32>,
+    apps: Grant<App>,
+    current_app: Cell<Option<AppId>>,
+}
+
+impl<'a> Logger<'a> {
+    /// `start_address` and `length` must lie within the kernel region of `storage`,
+    /// which reads from `flash`, and be multiples of the sector size.
+    pub fn new(
+        storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+        flash: &'a ext_flash::ExtFlash,
+        uart: &'a uart_mux::UartDevice<'a>,
+        start_address: usize,
+        length: usize,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> Logger<'a> {
+        Logger {
+            storage,
+            flash,
+            uart,
+            start_address,
+            capacity: length / RECORD_SIZE,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Uninitialized),
+            newest: Cell::new(None),
+            next_seq: Cell::new(0),
+            apps: grant,
+            current_app: Cell::new(None),
+        }
+    }
+
+    /// Scan the flash for the newest record, the log can be used once this completes.
+    pub fn initialize(&self) -> ReturnCode {
+        if self.state.get() != State::Uninitialized {
+            return ReturnCode::EALREADY;
+        }
+        self.newest.set(None);
+        self.read_slots(State::Scanning { slot: 0 }, 0, self.capacity)
+    }
+
+    /// Number of records currently held by the log.
+    pub fn len(&self) -> usize {
+        // The rest of the sector being filled has been erased
+        let next_slot = self.next_seq.get() as usize % self.capacity;
+        let erased = match next_slot % RECORDS_PER_SECTOR {
+            0 => 0,
+            used => RECORDS_PER_SECTOR - used,
+        };
+        cmp::min(self.next_seq.get() as usize, self.capacity - erased)
+    }
+
+    /// Append a record timestamped with the current RTC time.
+    pub fn append(&self, kind: u8, payload: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if payload.len() > MAX_PAYLOAD {
+            return ReturnCode::ESIZE;
+        }
+
+        let seq = self.next_seq.get();
+        let (sec, subsec) = unsafe { rtc::RTC.read_time() };
+        self.buffer.take().map_or(ReturnCode::ENOMEM, |buffer| {
+            encode_record(
+                &mut buffer[..RECORD_SIZE],
+                kind,
+                seq,
+                sec,
+                (subsec >> 16) as u16,
+                payload,
+            );
+
+            self.buffer.replace(buffer);
+
+            let slot = seq as usize % self.capacity;
+            if slot % RECORDS_PER_SECTOR != 0 {
+                return self.program_record();
+            }
+
+            // Moving into the next sector, which still holds the oldest records
+            let sector = self.slot_address(slot) / ext_flash::SECTOR_SIZE;
+            self.state.set(State::Erasing);
+            let rc = self.flash.erase_sector(sector);
+            if rc != ReturnCode::SUCCESS {
+                self.state.set(State::Idle);
+            }
+            rc
+        })
+    }
+
+    /// Program the record in the buffer into its erased slot.
+    fn program_record(&self) -> ReturnCode {
+        let address = self.slot_address(self.next_seq.get() as usize % self.capacity);
+        self.buffer.take().map_or(ReturnCode::ENOMEM, |buffer| {
+            self.state.set(State::Appending);
+            match self.flash.program(address, buffer, RECORD_SIZE) {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err((rc, buffer)) => {
+                    self.buffer.replace(buffer);
+                    self.state.set(State::Idle);
+                    rc
+                }
+            }
+        })
+    }
+
+    /// Continue after a scan once the slot of the next record is known to be erased.
+    fn check_next_slot(&self) {
+        let slot = self.next_seq.get() as usize % self.capacity;
+        if slot % RECORDS_PER_SECTOR == 0 {
+            // Erased by the next append anyway
+            self.state.set(State::Idle);
+        } else {
+            self.read_slots(State::Checking, slot, 1);
+        }
+    }
+
+    /// Write all records in the log to the UART console, oldest first.
+    pub fn dump(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        let last = self.next_seq.get();
+        let first = last - self.len() as u32;
+
+        self.buffer.take().map_or(ReturnCode::ENOMEM, |buffer| {
+            for byte in buffer[..12].iter_mut() {
+                *byte = 0;
+            }
+            buffer[0..4].copy_from_slice(DUMP_MAGIC);
+            buffer[4] = DUMP_VERSION;
+            buffer[5] = RECORD_SIZE as u8;
+            write_u32(&mut buffer[8..12], last - first);
+
+            self.state.set(State::Sending { seq: first, last });
+            self.uart.hold();
+            self.uart.transmit(buffer, 12);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn dump_from(&self, seq: u32, last: u32) -> ReturnCode {
+        if seq == last {
+            self.state.set(State::Idle);
+            self.uart.release();
+            self.operation_done(1, self.len());
+            return ReturnCode::SUCCESS;
+        }
+
+        // Read up to the end of the region or the buffer, whichever comes first
+        let slot = seq as usize % self.capacity;
+        let slots = cmp::min(
+            cmp::min((last - seq) as usize, self.capacity - slot),
+            BUFFER_SIZE / RECORD_SIZE,
+        );
+        self.read_slots(State::Dumping { seq, last }, slot, slots)
+    }
+
+    fn read_slots(&self, state: State, slot: usize, slots: usize) -> ReturnCode {
+        let slots = cmp::min(slots, BUFFER_SIZE / RECORD_SIZE);
+        self.buffer.take().map_or(ReturnCode::ENOMEM, |buffer| {
+            self.state.set(state);
+            let rc = self.storage
+                .read(buffer, self.slot_address(slot), slots * RECORD_SIZE);
+            if rc != ReturnCode::SUCCESS {
+                // A failed scan has to be retried before anything is appended
+                self.state.set(match state {
+                    State::Scanning { .. } | State::Checking => State::Uninitialized,
+                    _ => State::Idle,
+                });
+            }
+            rc
+        })
+    }
+
+    fn slot_address(&self, slot: usize) -> usize {
+        self.start_address + slot * RECORD_SIZE
+    }
+
+    fn operation_done(&self, event: usize, value: usize) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| cb.schedule(event, value, 0));
+            });
+        });
+    }
+
+    fn append_from_app(&self, appid: AppId, kind: u8) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        let mut payload = [0; MAX_PAYLOAD];
+        let rc = self.apps
+            .enter(appid, |app, _| {
+                app.buffer.as_ref().map_or(0, |buffer| {
+                    let len = cmp::min(buffer.len(), MAX_PAYLOAD);
+                    payload[..len].copy_from_slice(&buffer.as_ref()[..len]);
+                    len
+                })
+            })
+            .map(|len| {
+                self.current_app.set(Some(appid));
+                self.append(kind, &payload[..len])
+            })
+            .unwrap_or_else(|err| err.into());
+
+        if rc != ReturnCode::SUCCESS {
+            self.current_app.set(None);
+        }
+        rc
+    }
+
+    fn dump_from_app(&self, appid: AppId) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        self.current_app.set(Some(appid));
+        let rc = self.dump();
+        if rc != ReturnCode::SUCCESS {
+            self.current_app.set(None);
+        }
+        rc
+    }
+}
+
+impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for Logger<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.state.get() {
+            State::Scanning { slot } => {
+                let slots = length / RECORD_SIZE;
+                for record in buffer[..length].chunks(RECORD_SIZE) {
+                    if let Some(seq) = decode_sequence(record) {
+                        if self.newest.get().map_or(true, |newest| seq > newest) {
+                            self.newest.set(Some(seq));
+                        }
+                    }
+                }
+                self.buffer.replace(buffer);
+
+                let slot = slot + slots;
+                if slot < self.capacity {
+                    self.read_slots(State::Scanning { slot }, slot, self.capacity - slot);
+                } else {
+                    self.next_seq
+                        .set(self.newest.get().map_or(0, |newest| newest.wrapping_add(1)));
+                    self.check_next_slot();
+                }
+            }
+            State::Checking => {
+                let erased = buffer[..RECORD_SIZE].iter().all(|&byte| byte == 0xFF);
+                self.buffer.replace(buffer);
+                if erased {
+                    self.state.set(State::Idle);
+                } else {
+                    // Torn, programming on top of it would corrupt the new record
+                    self.next_seq.set(self.next_seq.get().wrapping_add(1));
+                    self.check_next_slot();
+                }
+            }
+            State::Dumping { seq, last } => {
+                let seq = seq + (length / RECORD_SIZE) as u32;
+                self.state.set(State::Sending { seq, last });
+                self.uart.transmit(buffer, length);
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        // Records are programmed directly, see `ext_flash::RawClient`
+        self.buffer.replace(buffer);
+    }
+}
+
+impl<'a> hil::uart::Client for Logger<'a> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: hil::uart::Error) {
+        self.buffer.replace(buffer);
+        if let State::Sending { seq, last } = self.state.get() {
+            if self.dump_from(seq, last) != ReturnCode::SUCCESS {
+                self.uart.release();
+                let first = last - self.len() as u32;
+                self.operation_done(1, (seq - first) as usize);
+            }
+        }
+    }
+
+    fn receive_complete(&self, _: &'static mut [u8], _: usize, _: hil::uart::Error) {}
+}
+
+impl<'a> ext_flash::RawClient for Logger<'a> {
+    fn erase_done(&self, error: hil::flash::Error) {
+        if self.state.get() != State::Erasing {
+            return;
+        }
+
+        let rc = match error {
+            hil::flash::Error::CommandComplete => self.program_record(),
+            hil::flash::Error::FlashError => ReturnCode::FAIL,
+        };
+        if rc != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+            self.operation_done(0, isize::from(rc) as usize);
+        }
+    }
+
+    fn program_done(&self, buffer: &'static mut [u8], error: hil::flash::Error) {
+        self.buffer.replace(buffer);
+        if self.state.get() != State::Appending {
+            return;
+        }
+
+        // A failed program may have left part of the record, so its slot is
+        // skipped either way
+        self.next_seq.set(self.next_seq.get().wrapping_add(1));
+        self.state.set(State::Idle);
+        let rc = match error {
+            hil::flash::Error::CommandComplete => ReturnCode::SUCCESS,
+            hil::flash::Error::FlashError => ReturnCode::FAIL,
+        };
+        self.operation_done(0, isize::from(rc) as usize);
+    }
+}
+
+impl<'a> Driver for Logger<'a> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.append_from_app(appid, data as u8),
+            2 => self.dump_from_app(appid),
+            3 => ReturnCode::SuccessWithValue { value: self.len() },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+fn encode_record(record: &mut [u8], kind: u8, seq: u32, sec: u32, subsec: u16, payload: &[u8]) {
+    for byte in record.iter_mut() {
+        *byte = 0xFF;
+    }
+    record[0] = RECORD_MAGIC;
+    record[1] = kind;
+    record[2] = payload.len() as u8;
+    write_u32(&mut record[4..8], seq);
+    write_u32(&mut record[8..12], sec);
+    record[12] = subsec as u8;
+    record[13] = (subsec >> 8) as u8;
+    record[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+
+    let crc = crc16(&record[..CRC_OFFSET]);
+    record[CRC_OFFSET] = crc as u8;
+    record[CRC_OFFSET + 1] = (crc >> 8) as u8;
+}
+
+/// Returns the sequence number of a record if it is intact.
+fn decode_sequence(record: &[u8]) -> Option<u32> {
+    if record.len() != RECORD_SIZE || record[0] != RECORD_MAGIC
+        || record[2] as usize > MAX_PAYLOAD
+    {
+        return None;
+    }
+
+    let crc = record[CRC_OFFSET] as u16 | (record[CRC_OFFSET + 1] as u16) << 8;
+    if crc16(&record[..CRC_OFFSET]) != crc {
+        return None;
+    }
+
+    Some(read_u32(&record[4..8]))
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -12,14 +12,35 @@ extern crate cc26xx;
 extern crate kernel;
 
 use cc26xx::trng;
-use cc26x0::{aon, gpio, peripherals, power, radio, rtc, uart};
+use cc26x0::{aon, ccfg, ext_flash, flash, gpio, i2c, peripherals, pins, power, radio, rtc, ssi,
+             timer, uart, watchdog};
+use pinmap::PINS;
 
 #[macro_use]
 pub mod io;
+pub mod buzzer;
+pub mod bytes;
+pub mod clock;
+pub mod debounce;
+pub mod gestures;
+pub mod gpio_port;
+pub mod loader;
+pub mod logger;
+pub mod pinmap;
+pub mod reed;
+pub mod ticks;
+pub mod uart_mux;
 
 // How should the kernel respond when a process faults.
 const FAULT_RESPONSE: kernel::process::FaultResponse = kernel::process::FaultResponse::Panic;
 
+// Button contacts bounce for a few ms, their level is sampled once quiet for this long.
+const BUTTON_SETTLE_MS: u32 = 20;
+const REED_SETTLE_MS: u32 = 10;
+
+// Reset the board when the kernel hangs for this long.
+const WATCHDOG_TIMEOUT_MS: u32 = 8000;
+
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 2;
 static mut PROCESSES: [Option<&'static mut kernel::Process<'static>>; NUM_PROCS] = [None, None];
@@ -27,6 +48,16 @@ static mut PROCESSES: [Option<&'static mut kernel::Process<'static>>; NUM_PROCS]
 #[link_section = ".app_memory"]
 static mut APP_MEMORY: [u8; 10240] = [0; 10240];
 
+// Customer configuration read by the boot ROM. The ROM bootloader stays disabled,
+// apps are installed through the serial loader instead.
+#[link_section = ".ccfg"]
+#[no_mangle]
+pub static CCFG: ccfg::Ccfg = ccfg::Ccfg::new()
+    .lf_clock(ccfg::LfClockSource::XoscLf)
+    .regulators(ccfg::Regulator::Dcdc, ccfg::Regulator::Dcdc)
+    .bootloader(ccfg::Enable::Disabled)
+    .debug_access(ccfg::Enable::Enabled);
+
 pub struct Platform {
     ble_radio: &'static capsules::ble_advertising_driver::BLE<
         'static,
@@ -34,14 +65,44 @@ pub struct Platform {
         capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
     >,
     gpio: &'static capsules::gpio::GPIO<'static, gpio::GPIOPin>,
+    gpio_port: &'static gpio_port::GpioPort,
     led: &'static capsules::led::LED<'static, gpio::GPIOPin>,
-    button: &'static capsules::button::Button<'static, gpio::GPIOPin>,
-    console: &'static capsules::console::Console<'static, uart::UART>,
+    button: &'static capsules::button::Button<
+        'static,
+        debounce::DebouncedPin<
+            'static,
+            gpio::GPIOPin,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+    >,
+    gestures: &'static gestures::ButtonGestures<
+        'static,
+        gpio::GPIOPin,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
+    reed: &'static reed::ReedRelay<
+        'static,
+        debounce::DebouncedPin<
+            'static,
+            gpio::GPIOPin,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+    >,
+    console: &'static capsules::console::Console<'static, uart_mux::UartDevice<'static>>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
         capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
     >,
     rng: &'static capsules::rng::SimpleRng<'static, trng::Trng>,
+    nonvolatile_storage:
+        &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    logger: &'static logger::Logger<'static>,
+    buzzer: &'static buzzer::Buzzer<
+        'static,
+        timer::Timer,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+    >,
+    clock: &'static clock::WallClock,
 }
 
 impl kernel::Platform for Platform {
@@ -57,6 +118,13 @@ impl kernel::Platform for Platform {
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
+            capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
+            logger::DRIVER_NUM => f(Some(self.logger)),
+            buzzer::DRIVER_NUM => f(Some(self.buzzer)),
+            clock::DRIVER_NUM => f(Some(self.clock)),
+            gpio_port::DRIVER_NUM => f(Some(self.gpio_port)),
+            gestures::DRIVER_NUM => f(Some(self.gestures)),
+            reed::DRIVER_NUM => f(Some(self.reed)),
             _ => f(None),
         }
     }
@@ -74,95 +142,60 @@ pub unsafe fn reset_handler() {
     peripherals::init();
 
     // Power on peripheral domain and gpio clocks
-    gpio::power_on_gpio();
+    gpio::power_on_gpio(&PINS);
+
+    // Holding button 1 during reset enters the serial process loader, the app
+    // memory is unused until the processes are loaded so it serves as scratch space.
+    if loader::button_held(&gpio::PORT[PINS.button_1]) {
+        uart::UART0.set_pins(PINS.uart_tx as u8, PINS.uart_rx as u8);
+        loader::run(&uart::UART0, &flash::FLASH, &mut APP_MEMORY);
+    }
 
     // LEDs
     let led_pins = static_init!(
         [(&'static gpio::GPIOPin, capsules::led::ActivationMode); 2],
         [
-            (&gpio::PORT[10], capsules::led::ActivationMode::ActiveHigh), // Red
-            (&gpio::PORT[15], capsules::led::ActivationMode::ActiveHigh)  // Green
+            (&gpio::PORT[PINS.led_red], capsules::led::ActivationMode::ActiveHigh),
+            (&gpio::PORT[PINS.led_green], capsules::led::ActivationMode::ActiveHigh)
         ]
     );
+    for &(led, _) in led_pins.iter() {
+        led.claim("LED");
+    }
     let led = static_init!(
         capsules::led::LED<'static, gpio::GPIOPin>,
         capsules::led::LED::new(led_pins)
     );
 
-    // BUTTONs
-    let button_pins = static_init!(
-        [(&'static gpio::GPIOPin, capsules::button::GpioMode); 2],
-        [
-            (&gpio::PORT[0], capsules::button::GpioMode::LowWhenPressed), // Button 2
-            (&gpio::PORT[4], capsules::button::GpioMode::LowWhenPressed)  // Button 1
-        ]
+    uart::UART0.set_pins(PINS.uart_tx as u8, PINS.uart_rx as u8);
+    // The console shares the UART with the log dump
+    let uart_mux = static_init!(
+        uart_mux::MuxUart<'static>,
+        uart_mux::MuxUart::new(&uart::UART0)
     );
-    let button = static_init!(
-        capsules::button::Button<'static, gpio::GPIOPin>,
-        capsules::button::Button::new(button_pins, kernel::Grant::create())
+    kernel::hil::uart::UART::set_client(&uart::UART0, uart_mux);
+    let console_uart = static_init!(
+        uart_mux::UartDevice<'static>,
+        uart_mux::UartDevice::new(uart_mux)
     );
-    for &(btn, _) in button_pins.iter() {
-        btn.set_client(button);
-    }
-
-    uart::UART0.set_pins(29, 28);
+    console_uart.setup();
     let console = static_init!(
-        capsules::console::Console<uart::UART>,
+        capsules::console::Console<uart_mux::UartDevice>,
         capsules::console::Console::new(
-            &uart::UART0,
+            console_uart,
             115200,
             &mut capsules::console::WRITE_BUF,
             &mut capsules::console::READ_BUF,
             kernel::Grant::create()
         )
     );
-    kernel::hil::uart::UART::set_client(&uart::UART0, console);
+    kernel::hil::uart::UART::set_client(console_uart, console);
     console.initialize();
 
     // Attach the kernel debug interface to this console
     let kc = static_init!(capsules::console::App, capsules::console::App::default());
     kernel::debug::assign_console_driver(Some(console), kc);
 
-    // Setup for remaining GPIO pins
-    let gpio_pins = static_init!(
-        [&'static gpio::GPIOPin; 26],
-        [
-            &gpio::PORT[1],
-            &gpio::PORT[2],
-            &gpio::PORT[3],
-            &gpio::PORT[5],
-            &gpio::PORT[6],
-            &gpio::PORT[7],
-            &gpio::PORT[8],
-            &gpio::PORT[9],
-            &gpio::PORT[11],
-            &gpio::PORT[12],
-            &gpio::PORT[13],
-            &gpio::PORT[14],
-            &gpio::PORT[16],
-            &gpio::PORT[17],
-            &gpio::PORT[18],
-            &gpio::PORT[19],
-            &gpio::PORT[20],
-            &gpio::PORT[21],
-            &gpio::PORT[22],
-            &gpio::PORT[23],
-            &gpio::PORT[24],
-            &gpio::PORT[25],
-            &gpio::PORT[26],
-            &gpio::PORT[27],
-            &gpio::PORT[30],
-            &gpio::PORT[31]
-        ]
-    );
-    let gpio = static_init!(
-        capsules::gpio::GPIO<'static, gpio::GPIOPin>,
-        capsules::gpio::GPIO::new(gpio_pins)
-    );
-    for pin in gpio_pins.iter() {
-        pin.set_client(gpio);
-    }
-
     let rtc = &rtc::RTC;
     rtc.start();
 
@@ -189,6 +222,129 @@ pub unsafe fn reset_handler() {
         capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
     );
 
+    // BUTTONs, debounced so contact bounce doesn't reach apps
+    for &pin in [PINS.button_1, PINS.button_2].iter() {
+        gpio::PORT[pin].claim("Button");
+        kernel::hil::gpio::Pin::make_input(&gpio::PORT[pin]);
+        kernel::hil::gpio::PinCtl::set_input_mode(
+            &gpio::PORT[pin],
+            kernel::hil::gpio::InputMode::PullUp,
+        );
+    }
+    let button1_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let button1 = static_init!(
+        debounce::DebouncedPin<
+            'static,
+            gpio::GPIOPin,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        debounce::DebouncedPin::new(&gpio::PORT[PINS.button_1], button1_alarm, BUTTON_SETTLE_MS)
+    );
+    button1_alarm.set_client(button1);
+    gpio::PORT[PINS.button_1].set_client(button1);
+    let button2_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let button2 = static_init!(
+        debounce::DebouncedPin<
+            'static,
+            gpio::GPIOPin,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        debounce::DebouncedPin::new(&gpio::PORT[PINS.button_2], button2_alarm, BUTTON_SETTLE_MS)
+    );
+    button2_alarm.set_client(button2);
+    gpio::PORT[PINS.button_2].set_client(button2);
+
+    let button_pins = static_init!(
+        [(
+            &'static debounce::DebouncedPin<
+                'static,
+                gpio::GPIOPin,
+                capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            >,
+            capsules::button::GpioMode
+        ); 2],
+        [
+            (button2, capsules::button::GpioMode::LowWhenPressed),
+            (button1, capsules::button::GpioMode::LowWhenPressed)
+        ]
+    );
+    let button = static_init!(
+        capsules::button::Button<
+            'static,
+            debounce::DebouncedPin<
+                'static,
+                gpio::GPIOPin,
+                capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            >,
+        >,
+        capsules::button::Button::new(button_pins, kernel::Grant::create())
+    );
+    for &(btn, _) in button_pins.iter() {
+        btn.set_client(button);
+    }
+
+    // Short/long press and double click of the same buttons
+    let gestures_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let gestures = static_init!(
+        gestures::ButtonGestures<
+            'static,
+            gpio::GPIOPin,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        gestures::ButtonGestures::new(
+            button_pins,
+            gestures_virtual_alarm,
+            kernel::Grant::create()
+        )
+    );
+    gestures_virtual_alarm.set_client(gestures);
+    for (index, &(btn, _)) in button_pins.iter().enumerate() {
+        btn.set_level_client(gestures, index);
+    }
+
+    // Reed relay, changes wake the chip from deep sleep while an app listens
+    gpio::PORT[PINS.reed_relay].claim("Reed relay");
+    kernel::hil::gpio::Pin::make_input(&gpio::PORT[PINS.reed_relay]);
+    kernel::hil::gpio::PinCtl::set_input_mode(
+        &gpio::PORT[PINS.reed_relay],
+        kernel::hil::gpio::InputMode::PullDown,
+    );
+    let reed_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let reed_pin = static_init!(
+        debounce::DebouncedPin<
+            'static,
+            gpio::GPIOPin,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        debounce::DebouncedPin::new(&gpio::PORT[PINS.reed_relay], reed_alarm, REED_SETTLE_MS)
+    );
+    reed_alarm.set_client(reed_pin);
+    gpio::PORT[PINS.reed_relay].set_client(reed_pin);
+    let reed = static_init!(
+        reed::ReedRelay<
+            'static,
+            debounce::DebouncedPin<
+                'static,
+                gpio::GPIOPin,
+                capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+            >,
+        >,
+        reed::ReedRelay::new(reed_pin, true, kernel::Grant::create())
+    );
+    reed_pin.set_client(reed);
+
     //trng::TRNG.enable();
     let rng = static_init!(
         capsules::rng::SimpleRng<'static, trng::Trng>,
@@ -221,17 +377,139 @@ pub unsafe fn reset_handler() {
     );
     ble_radio_virtual_alarm.set_client(ble_radio);
 
+    // Buzzer, driven by the PWM output of GPT0A
+    let buzzer_pin = static_init!(
+        timer::PwmPin,
+        timer::PwmPin::new(timer::Half::A, PINS.buzzer as u8)
+    );
+    let buzzer_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let buzzer = static_init!(
+        buzzer::Buzzer<
+            'static,
+            timer::Timer,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, rtc::Rtc>,
+        >,
+        buzzer::Buzzer::new(
+            &timer::GPT0,
+            buzzer_pin,
+            buzzer_virtual_alarm,
+            &mut buzzer::BUFFER,
+            kernel::Grant::create()
+        )
+    );
+    buzzer_virtual_alarm.set_client(buzzer);
+
+    let clock = static_init!(
+        clock::WallClock,
+        clock::WallClock::new(kernel::Grant::create())
+    );
+
+    // External flash, the first 128KB are available to apps and the next 128KB to the kernel
+    ssi::SSI0.set_pins(PINS.spi_mosi as u8, PINS.spi_miso as u8, PINS.spi_clk as u8);
+    kernel::hil::spi::SpiMaster::specify_chip_select(&ssi::SSI0, PINS.flash_cs as u8);
+    kernel::hil::spi::SpiMaster::set_client(&ssi::SSI0, &ext_flash::FLASH);
+    ext_flash::FLASH.initialize(&mut ext_flash::TX_BUF, &mut ext_flash::RX_BUF);
+
+    let flash_page = static_init!(ext_flash::ExtFlashPage, ext_flash::ExtFlashPage::default());
+    let nv_to_page = static_init!(
+        capsules::nonvolatile_to_pages::NonvolatileToPages<'static, ext_flash::ExtFlash>,
+        capsules::nonvolatile_to_pages::NonvolatileToPages::new(&ext_flash::FLASH, flash_page)
+    );
+    kernel::hil::flash::HasClient::set_client(&ext_flash::FLASH, nv_to_page);
+
+    let nonvolatile_storage = static_init!(
+        capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+        capsules::nonvolatile_storage_driver::NonvolatileStorage::new(
+            nv_to_page,
+            kernel::Grant::create(),
+            0x00000, // Start address for userspace accessible region
+            0x20000, // Length of userspace accessible region
+            0x20000, // Start address of kernel accessible region
+            0x20000, // Length of kernel accessible region
+            &mut capsules::nonvolatile_storage_driver::BUFFER
+        )
+    );
+    kernel::hil::nonvolatile_storage::NonvolatileStorage::set_client(
+        nv_to_page,
+        nonvolatile_storage,
+    );
+
+    // Sensor data log, occupying the whole kernel region of the external flash
+    let logger_uart = static_init!(
+        uart_mux::UartDevice<'static>,
+        uart_mux::UartDevice::new(uart_mux)
+    );
+    logger_uart.setup();
+    let logger = static_init!(
+        logger::Logger<'static>,
+        logger::Logger::new(
+            nonvolatile_storage,
+            &ext_flash::FLASH,
+            logger_uart,
+            0x20000,
+            0x20000,
+            &mut logger::BUFFER,
+            kernel::Grant::create()
+        )
+    );
+    kernel::hil::nonvolatile_storage::NonvolatileStorage::set_client(nonvolatile_storage, logger);
+    ext_flash::FLASH.set_raw_client(logger);
+    kernel::hil::uart::UART::set_client(logger_uart, logger);
+    logger.initialize();
+
+    // Sensor buses, the sensor drivers switch between them at runtime
+    i2c::I2C0.claim_pins(&PINS);
+
+    // Userspace GPIO gets every pin not claimed by a driver above
+    let gpio_pins = static_init!(
+        [&'static gpio::GPIOPin; pins::NUM_PINS],
+        [&gpio::PORT[0]; pins::NUM_PINS]
+    );
+    let mut num_gpio_pins = 0;
+    let mut gpio_mask = 0;
+    for pin in 0..pins::NUM_PINS {
+        if !pins::is_claimed(pin) {
+            gpio::PORT[pin].claim("userspace GPIO");
+            gpio_pins[num_gpio_pins] = &gpio::PORT[pin];
+            num_gpio_pins += 1;
+            gpio_mask |= 1 << pin;
+        }
+    }
+    let gpio_pins = &gpio_pins[..num_gpio_pins];
+    let gpio = static_init!(
+        capsules::gpio::GPIO<'static, gpio::GPIOPin>,
+        capsules::gpio::GPIO::new(gpio_pins)
+    );
+    for pin in gpio_pins.iter() {
+        pin.set_client(gpio);
+    }
+    // The same pins, switched together
+    let gpio_port = static_init!(
+        gpio_port::GpioPort,
+        gpio_port::GpioPort::new(&gpio::PORT, gpio_mask)
+    );
+
     let sensortag = Platform {
         ble_radio,
         gpio,
+        gpio_port,
         led,
         button,
+        gestures,
+        reed,
         console,
         alarm,
         rng,
+        nonvolatile_storage,
+        logger,
+        buzzer,
+        clock,
     };
 
-    let mut chip = cc26x0::chip::Cc26x0::new();
+    let mut chip = cc26x0::chip::Cc26x0::new(&PINS);
 
     debug!("Initialization complete. Entering main loop\r");
     extern "C" {
@@ -246,6 +524,8 @@ pub unsafe fn reset_handler() {
         FAULT_RESPONSE,
     );
 
+    watchdog::WATCHDOG.start(WATCHDOG_TIMEOUT_MS, watchdog::Warning::Interrupt);
+
     kernel::main(
         &sensortag,
         &mut chip,
@@ -0,0 +1,47 @@
+//! Pin map of the CC2650 SensorTag
+//!
+//! The DIO of every signal on the board. The chip crate takes the same table for
+//! its defaults, see `cc26x0::pinmap`. A port to another cc26x0 board adds the
+//! table of that board and selects it as `PINS`.
+
+use cc26x0::pinmap::PinMap;
+
+/// The CC2650 SensorTag (CC2650STK).
+pub const SENSORTAG: PinMap = PinMap {
+    led_red: 10,
+    led_green: 15,
+    button_1: 4,
+    button_2: 0,
+    reed_relay: 3,
+    buzzer: 21,
+
+    uart_tx: 29,
+    uart_rx: 28,
+
+    i2c_sda: 5,
+    i2c_scl: 6,
+    i2c_sda_hp: 8,
+    i2c_scl_hp: 9,
+    tmp_rdy: 1,
+    mpu_int: 7,
+    mpu_power: 12,
+
+    spi_mosi: 19,
+    spi_miso: 18,
+    spi_clk: 17,
+    flash_cs: 14,
+
+    mic_power: 13,
+    audio_di: 2,
+    audio_clk: 11,
+
+    devpack_cs: 20,
+    dp0: 25,
+    dp1: 24,
+    dp2: 23,
+    dp3: 27,
+    devpack_id: 30,
+};
+
+/// The pin map of the board the kernel is built for.
+pub static PINS: PinMap = SENSORTAG;
@@ -0,0 +1,111 @@
+//! Reed relay
+//!
+//! The SensorTag has a reed relay that closes while a magnet is close, e.g. to
+//! detect an opened door or a removed enclosure lid. Its pin is watched for
+//! changes as long as an app has subscribed. A change wakes the chip from deep
+//! sleep through the GPIO edge detection, so the app is notified without polling.
+//!
+//! The pin is expected to be debounced (see `debounce`), a relay bounces like a
+//! button when the magnet moves past it.
+//!
+//! Userspace interface
+//! -------------------
+//!
+//! * subscribe 0: Callback `(closed, 0, 0)` on every change, with closed 1 while
+//!   a magnet is close.
+//! * command 0: Check if the driver exists.
+//! * command 1: Get the current state, 1 while a magnet is close.
+
+use core::cell::Cell;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil;
+use kernel::hil::gpio::InterruptMode;
+
+pub const DRIVER_NUM: usize = 0x90004;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct ReedRelay<'a, P: hil::gpio::Pin + 'a> {
+    pin: &'a P,
+    // The level of the pin while the relay is closed
+    closed_high: bool,
+    listening: Cell<bool>,
+    apps: Grant<App>,
+}
+
+impl<'a, P: hil::gpio::Pin + 'a> ReedRelay<'a, P> {
+    pub fn new(pin: &'a P, closed_high: bool, grant: Grant<App>) -> ReedRelay<'a, P> {
+        ReedRelay {
+            pin,
+            closed_high,
+            listening: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pin.read() == self.closed_high
+    }
+
+    /// Watch the pin while any app has a callback.
+    fn update_interrupt(&self) {
+        let mut listen = false;
+        self.apps.each(|app| listen |= app.callback.is_some());
+        if listen == self.listening.get() {
+            return;
+        }
+        self.listening.set(listen);
+
+        if listen {
+            self.pin.enable_interrupt(0, InterruptMode::EitherEdge);
+        } else {
+            self.pin.disable_interrupt();
+        }
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a> hil::gpio::Client for ReedRelay<'a, P> {
+    fn fired(&self, _: usize) {
+        let closed = self.is_closed();
+        self.apps.each(|app| {
+            app.callback
+                .map(|mut cb| cb.schedule(closed as usize, 0, 0));
+        });
+    }
+}
+
+impl<'a, P: hil::gpio::Pin + 'a> Driver for ReedRelay<'a, P> {
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => {
+                let result = self.apps
+                    .enter(appid, |app, _| {
+                        app.callback = callback;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                self.update_interrupt();
+                result
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, _: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => ReturnCode::SuccessWithValue {
+                value: self.is_closed() as usize,
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Durations in ticks of the alarm a driver runs on.
+
+use kernel::hil::time::{Alarm, Frequency};
+
+/// Number of ticks of alarm `A` in `ms` milliseconds, rounded down.
+pub fn ms_to_ticks<A: Alarm>(ms: u32) -> u32 {
+    let frequency = <A::Frequency as Frequency>::frequency() as u64;
+    (ms as u64 * frequency / 1000) as u32
+}
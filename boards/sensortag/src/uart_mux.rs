@@ -0,0 +1,172 @@
+//! Shared console UART
+//!
+//! Lets several users, like `capsules::console` and the log dump, share the
+//! console UART. Each `UartDevice` implements the UART HIL on its own.
+//! Transmissions are queued and sent one buffer at a time, taking turns between
+//! the devices. A device can hold the UART to send a stream of buffers that must
+//! not be interleaved with other output, the other devices wait meanwhile.
+//!
+//! A receive goes to the device that asked for it, one receive at a time.
+
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::uart;
+
+pub struct MuxUart<'a> {
+    uart: &'a uart::UART,
+    devices: List<'a, UartDevice<'a>>,
+    transmitting: Cell<Option<&'a UartDevice<'a>>>,
+    receiving: Cell<Option<&'a UartDevice<'a>>>,
+    holder: Cell<Option<&'a UartDevice<'a>>>,
+}
+
+impl<'a> MuxUart<'a> {
+    pub fn new(uart: &'a uart::UART) -> MuxUart<'a> {
+        MuxUart {
+            uart,
+            devices: List::new(),
+            transmitting: Cell::new(None),
+            receiving: Cell::new(None),
+            holder: Cell::new(None),
+        }
+    }
+
+    fn find(&self, device: &UartDevice<'a>) -> Option<&'a UartDevice<'a>> {
+        self.devices.iter().find(|d| ptr::eq(*d, device))
+    }
+
+    /// Send the next queued buffer, preferring devices other than `last`.
+    fn start_transmit(&self, last: Option<&'a UartDevice<'a>>) {
+        if self.transmitting.get().is_some() {
+            return;
+        }
+
+        let mut next = None;
+        for device in self.devices.iter() {
+            let allowed = self.holder
+                .get()
+                .map_or(true, |holder| ptr::eq(holder, device));
+            if !allowed || device.tx_buffer.is_none() {
+                continue;
+            }
+            let is_last = last.map_or(false, |last| ptr::eq(last, device));
+            if next.is_none() || !is_last {
+                next = Some(device);
+            }
+            if !is_last {
+                break;
+            }
+        }
+
+        next.map(|device| {
+            device.tx_buffer.take().map(|buffer| {
+                self.transmitting.set(Some(device));
+                self.uart.transmit(buffer, device.tx_len.get());
+            });
+        });
+    }
+}
+
+impl<'a> uart::Client for MuxUart<'a> {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: uart::Error) {
+        let device = self.transmitting.take();
+        device.map(move |device| {
+            device
+                .client
+                .get()
+                .map(move |client| client.transmit_complete(tx_buffer, error));
+        });
+        self.start_transmit(device);
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        self.receiving.take().map(move |device| {
+            device
+                .client
+                .get()
+                .map(move |client| client.receive_complete(rx_buffer, rx_len, error));
+        });
+    }
+}
+
+pub struct UartDevice<'a> {
+    mux: &'a MuxUart<'a>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    client: Cell<Option<&'static uart::Client>>,
+    next: ListLink<'a, UartDevice<'a>>,
+}
+
+impl<'a> UartDevice<'a> {
+    pub fn new(mux: &'a MuxUart<'a>) -> UartDevice<'a> {
+        UartDevice {
+            mux,
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            client: Cell::new(None),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Add the device to the mux, once it is at its final address.
+    pub fn setup(&'a self) {
+        self.mux.devices.push_head(self);
+    }
+
+    /// Keep the other devices from transmitting until `release`.
+    pub fn hold(&self) {
+        self.mux.holder.set(self.mux.find(self));
+    }
+
+    pub fn release(&self) {
+        self.mux.holder.set(None);
+        self.mux.start_transmit(None);
+    }
+}
+
+impl<'a> ListNode<'a, UartDevice<'a>> for UartDevice<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, UartDevice<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> uart::UART for UartDevice<'a> {
+    fn set_client(&self, client: &'static uart::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn init(&self, params: uart::UARTParams) {
+        self.mux.uart.init(params);
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        let in_flight = self.mux
+            .transmitting
+            .get()
+            .map_or(false, |device| ptr::eq(device, self));
+        if self.tx_buffer.is_some() || in_flight {
+            self.client.get().map(move |client| {
+                client.transmit_complete(tx_data, uart::Error::RepeatCallError);
+            });
+            return;
+        }
+
+        self.tx_buffer.replace(tx_data);
+        self.tx_len.set(tx_len);
+        self.mux.start_transmit(None);
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        if self.mux.receiving.get().is_some() {
+            self.client.get().map(move |client| {
+                client.receive_complete(rx_buffer, 0, uart::Error::RepeatCallError);
+            });
+            return;
+        }
+
+        self.mux.receiving.set(self.mux.find(self));
+        self.mux.uart.receive(rx_buffer, rx_len);
+    }
+}
@@ -2,8 +2,13 @@
 //!
 //! AON is a set of peripherals which is _always on_ (eg. the RTC, MCU, etc).
 //!
-//! The current configuration disables all wake-up selectors, since the
-//! MCU never go to sleep and is always active.
+//! By default the MCU is woken from deep sleep by the RTC combined event, which
+//! covers every enabled RTC channel, and by the IO event, an edge on any pin with
+//! its edge interrupt enabled. Further wake-up sources are selected with
+//! `set_mcu_wakeup_source`.
+//!
+//! The AON events also feed the capture of the RTC, selected with
+//! `set_rtc_capture_source`.
 
 use kernel::common::VolatileCell;
 use kernel::common::regs::{ReadOnly, ReadWrite};
@@ -23,6 +28,39 @@ pub struct AonEventRegisters {
     rtc_sel: VolatileCell<u32>,          // RTC Capture event selector for AON_RTC
 }
 
+/// AON event sources, used to select what wakes the MCU from deep sleep and what
+/// the RTC captures.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Event {
+    // Edge detected on a DIO, 0 to 31
+    Dio(u8),
+    // Edge detected on any DIO with its edge interrupt enabled
+    Io,
+    RtcChannel0,
+    RtcChannel1,
+    RtcChannel2,
+    // Any of the RTC channels in the RTC combined event (delayed by RTC EV_DELAY, 0)
+    RtcCombined,
+    None,
+}
+
+impl Event {
+    fn id(self) -> u32 {
+        match self {
+            Event::Dio(dio) => (dio & 0x1F) as u32,
+            Event::Io => 0x20,
+            Event::RtcChannel0 => 0x23,
+            Event::RtcChannel1 => 0x24,
+            Event::RtcChannel2 => 0x25,
+            Event::RtcCombined => 0x29,
+            Event::None => 0x3F,
+        }
+    }
+}
+
+/// Number of MCU wake-up selectors (WU0 to WU3).
+pub const MCU_WAKEUP_SOURCES: usize = 4;
+
 #[repr(C)]
 struct AonWucRegisters {
     mcu_clk: ReadWrite<u32, McuClk::Register>,
@@ -153,11 +191,13 @@ impl Aon {
         // Default to no events at all
         regs.aux_wu_sel.set(0x3F3F3F3F);
 
-        // Set RTC CH1 as a wakeup source by default
-        regs.mcu_wu_sel.set(0x3F3F3F24);
+        // Wake up on any enabled RTC channel and on GPIO interrupts by default
+        regs.mcu_wu_sel.set(0x3F3F3F3F);
+        self.set_mcu_wakeup_source(0, Event::RtcCombined);
+        self.set_mcu_wakeup_source(1, Event::Io);
 
-        // Disable RTC combined event
-        regs.rtc_sel.set(0x0000003F);
+        // Nothing to capture until the RTC asks for it
+        self.set_rtc_capture_source(Event::None);
 
         // The default reset value is 0x002B2B2B. However, 0x2b for each
         // programmable event corresponds to a JTAG event; which is fired
@@ -168,6 +208,24 @@ impl Aon {
         regs.event_to_mcu_sel.set(0x003F3F3F);
     }
 
+    /// Select the event of one of the MCU wake-up selectors (0 to 3).
+    pub fn set_mcu_wakeup_source(&self, source: usize, event: Event) {
+        if source >= MCU_WAKEUP_SOURCES {
+            return;
+        }
+
+        let regs: &AonEventRegisters = unsafe { &*self.event_regs };
+        let shift = source * 8;
+        let wu_sel = regs.mcu_wu_sel.get() & !(0x3F << shift);
+        regs.mcu_wu_sel.set(wu_sel | event.id() << shift);
+    }
+
+    /// Select the event the RTC captures on channel 1, see `rtc::Rtc::start_capture`.
+    pub fn set_rtc_capture_source(&self, event: Event) {
+        let regs: &AonEventRegisters = unsafe { &*self.event_regs };
+        regs.rtc_sel.set(event.id());
+    }
+
     pub fn set_dcdc_enabled(&self, enabled: bool) {
         let regs: &AonSysctlRegisters = unsafe { &*self.aon_sysctl_regs };
         if enabled {
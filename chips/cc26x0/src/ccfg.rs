@@ -0,0 +1,225 @@
+//! Customer configuration (CCFG)
+//!
+//! The last 88 bytes of the flash (CCFG_ORIGIN in `layout.ld`) hold the customer
+//! configuration, which the boot ROM reads before the kernel runs. It selects the
+//! low frequency clock source, the regulators, whether the ROM bootloader may be
+//! entered and which debug ports are accessible.
+//!
+//! Boards build the configuration from `Ccfg::new()`, which holds the TI defaults,
+//! and place it in the `.ccfg` section:
+//!
+//! ```rust,ignore
+//! #[link_section = ".ccfg"]
+//! #[no_mangle]
+//! pub static CCFG: ccfg::Ccfg = ccfg::Ccfg::new()
+//!     .lf_clock(ccfg::LfClockSource::XoscLf)
+//!     .debug_access(ccfg::Enable::Disabled);
+//! ```
+//!
+//! All setters are `const fn`, and values without a type of their own (DIO numbers,
+//! the RTC increment) are checked while the static is evaluated, so an invalid
+//! configuration fails to compile instead of producing an unbootable image.
+//!
+//! For the register descriptions see the CCFG chapter in the cc2650 technical
+//! reference manual.
+
+/// Encoding used by the CCFG for features that are enabled or disabled.
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum Enable {
+    Disabled = 0x00,
+    Enabled = 0xC5,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum LfClockSource {
+    /// 31.25kHz derived from the 24MHz crystal oscillator
+    XoscHfDerived = 0x0,
+    /// External clock on a DIO, see `Ccfg::external_lf_clock`
+    External = 0x1,
+    /// 32.768kHz crystal oscillator
+    XoscLf = 0x2,
+    /// 32kHz RC oscillator
+    RcoscLf = 0x3,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum Regulator {
+    Dcdc = 0x0,
+    Gldo = 0x1,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum Level {
+    Low = 0x0,
+    High = 0x1,
+}
+
+/// A DIO number, `Dio::new` refuses to compile for pins the chip doesn't have.
+#[derive(Clone, Copy)]
+pub struct Dio(u32);
+
+impl Dio {
+    pub const fn new(dio: u32) -> Dio {
+        Dio(check_bits(dio, 5))
+    }
+}
+
+/// Evaluates to `value`, or fails const evaluation if it doesn't fit in `bits`.
+const fn check_bits(value: u32, bits: u32) -> u32 {
+    value + [0; 1][(value >> bits) as usize]
+}
+
+// EXT_LF_CLK
+const EXT_LF_CLK_DIO_SHIFT: u32 = 24;
+const EXT_LF_CLK_RTC_INCREMENT_MASK: u32 = 0x00FF_FFFF;
+
+// MODE_CONF
+const MODE_CONF_DCDC_RECHARGE_SHIFT: u32 = 27;
+const MODE_CONF_DCDC_ACTIVE_SHIFT: u32 = 26;
+const MODE_CONF_SCLK_LF_OPTION_SHIFT: u32 = 22;
+const MODE_CONF_SCLK_LF_OPTION_MASK: u32 = 0x3 << MODE_CONF_SCLK_LF_OPTION_SHIFT;
+
+// BL_CONFIG
+const BL_CONFIG_BOOTLOADER_ENABLE_SHIFT: u32 = 24;
+const BL_CONFIG_BL_LEVEL_SHIFT: u32 = 16;
+const BL_CONFIG_BL_PIN_NUMBER_SHIFT: u32 = 8;
+const BL_CONFIG_RESERVED: u32 = 0x00FE_0000;
+
+// CCFG_TAP_DAP_0
+const TAP_DAP_0_CPU_DAP_SHIFT: u32 = 16;
+const TAP_DAP_0_PRCM_TAP_SHIFT: u32 = 8;
+// CCFG_TAP_DAP_1
+const TAP_DAP_1_PBIST2_TAP_SHIFT: u32 = 16;
+const TAP_DAP_1_PBIST1_TAP_SHIFT: u32 = 8;
+const TAP_DAP_RESERVED: u32 = 0xFF00_0000;
+
+#[repr(C)]
+pub struct Ccfg {
+    ext_lf_clk: u32,
+    mode_conf_1: u32,
+    size_and_dis_flags: u32,
+    mode_conf: u32,
+    volt_load_0: u32,
+    volt_load_1: u32,
+    rtc_offset: u32,
+    freq_offset: u32,
+    ieee_mac_0: u32,
+    ieee_mac_1: u32,
+    ieee_ble_0: u32,
+    ieee_ble_1: u32,
+    bl_config: u32,
+    erase_conf: u32,
+    ccfg_ti_options: u32,
+    ccfg_tap_dap_0: u32,
+    ccfg_tap_dap_1: u32,
+    image_valid_conf: u32,
+    ccfg_prot_31_0: u32,
+    ccfg_prot_63_32: u32,
+    ccfg_prot_95_64: u32,
+    ccfg_prot_127_96: u32,
+}
+
+impl Ccfg {
+    /// The default configuration from TI: LF crystal oscillator, DCDC in active and
+    /// recharge mode, ROM bootloader disabled and all debug ports accessible.
+    pub const fn new() -> Ccfg {
+        Ccfg {
+            ext_lf_clk: 0x0180_0000,
+            mode_conf_1: 0xFF82_0010,
+            // Size of the CCFG (0x58) and the disable flags
+            size_and_dis_flags: 0x0058_FFFD,
+            mode_conf: 0xF3BF_FF3A,
+            volt_load_0: 0xFFFF_FFFF,
+            volt_load_1: 0xFFFF_FFFF,
+            rtc_offset: 0xFFFF_FFFF,
+            freq_offset: 0xFFFF_FFFF,
+            // Use the addresses from the factory configuration
+            ieee_mac_0: 0xFFFF_FFFF,
+            ieee_mac_1: 0xFFFF_FFFF,
+            ieee_ble_0: 0xFFFF_FFFF,
+            ieee_ble_1: 0xFFFF_FFFF,
+            bl_config: 0x00FE_FFFF,
+            erase_conf: 0xFFFF_FFFF,
+            ccfg_ti_options: 0xFFFF_FF00,
+            ccfg_tap_dap_0: 0xFFC5_C5C5,
+            ccfg_tap_dap_1: 0xFFC5_C5C5,
+            // Zero marks the image in flash as valid, the ROM boots it
+            image_valid_conf: 0x0000_0000,
+            ccfg_prot_31_0: 0xFFFF_FFFF,
+            ccfg_prot_63_32: 0xFFFF_FFFF,
+            ccfg_prot_95_64: 0xFFFF_FFFF,
+            ccfg_prot_127_96: 0xFFFF_FFFF,
+        }
+    }
+
+    pub const fn lf_clock(self, source: LfClockSource) -> Ccfg {
+        Ccfg {
+            mode_conf: (self.mode_conf & !MODE_CONF_SCLK_LF_OPTION_MASK)
+                | (source as u32) << MODE_CONF_SCLK_LF_OPTION_SHIFT,
+            ..self
+        }
+    }
+
+    /// Use an external clock of `frequency_hz` on `dio` as the LF clock.
+    pub const fn external_lf_clock(self, dio: Dio, frequency_hz: u32) -> Ccfg {
+        Ccfg {
+            // The RTC is incremented by 2^38 / frequency on every clock edge
+            ext_lf_clk: dio.0 << EXT_LF_CLK_DIO_SHIFT
+                | check_bits(((1u64 << 38) / frequency_hz as u64) as u32, 24)
+                    & EXT_LF_CLK_RTC_INCREMENT_MASK,
+            ..self.lf_clock(LfClockSource::External)
+        }
+    }
+
+    /// Select the regulators used while active, and while recharging in standby.
+    pub const fn regulators(self, active: Regulator, recharge: Regulator) -> Ccfg {
+        Ccfg {
+            mode_conf: self.mode_conf
+                & !(1 << MODE_CONF_DCDC_ACTIVE_SHIFT | 1 << MODE_CONF_DCDC_RECHARGE_SHIFT)
+                | (active as u32) << MODE_CONF_DCDC_ACTIVE_SHIFT
+                | (recharge as u32) << MODE_CONF_DCDC_RECHARGE_SHIFT,
+            ..self
+        }
+    }
+
+    /// Allow the ROM bootloader to run when the flash image is invalid, without
+    /// a backdoor pin.
+    pub const fn bootloader(self, enable: Enable) -> Ccfg {
+        Ccfg {
+            bl_config: (enable as u32) << BL_CONFIG_BOOTLOADER_ENABLE_SHIFT
+                | BL_CONFIG_RESERVED | 0xFFFF,
+            ..self
+        }
+    }
+
+    /// Enable the ROM bootloader and enter it when `dio` is at `level` during reset.
+    pub const fn bootloader_backdoor(self, dio: Dio, level: Level) -> Ccfg {
+        Ccfg {
+            bl_config: (Enable::Enabled as u32) << BL_CONFIG_BOOTLOADER_ENABLE_SHIFT
+                | BL_CONFIG_RESERVED
+                | (level as u32) << BL_CONFIG_BL_LEVEL_SHIFT
+                | dio.0 << BL_CONFIG_BL_PIN_NUMBER_SHIFT
+                | Enable::Enabled as u32,
+            ..self
+        }
+    }
+
+    /// Enable or lock all JTAG TAPs and the CPU debug access port.
+    pub const fn debug_access(self, enable: Enable) -> Ccfg {
+        Ccfg {
+            ccfg_tap_dap_0: TAP_DAP_RESERVED
+                | (enable as u32) << TAP_DAP_0_CPU_DAP_SHIFT
+                | (enable as u32) << TAP_DAP_0_PRCM_TAP_SHIFT
+                | enable as u32,
+            ccfg_tap_dap_1: TAP_DAP_RESERVED
+                | (enable as u32) << TAP_DAP_1_PBIST2_TAP_SHIFT
+                | (enable as u32) << TAP_DAP_1_PBIST1_TAP_SHIFT
+                | enable as u32,
+            ..self
+        }
+    }
+}
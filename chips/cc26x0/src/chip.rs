@@ -1,19 +1,36 @@
-use cortexm3::{self, nvic};
-use cc26xx::gpio;
+use cortexm3::nvic;
 use cc26xx::peripheral_interrupts::*;
 
 const X0_RF_CPE1: u32 = 2;
+const X0_SSI0: u32 = 7;
 const X0_RF_CPE0: u32 = 9;
 const X0_RF_CMD_ACK: u32 = 11;
+const X0_WATCHDOG: u32 = 14;
+const X0_FLASH: u32 = 26;
 
 use radio;
 use timer;
 use uart;
+use ssi;
+use flash;
+use gpio;
 use kernel;
 use rtc;
+use watchdog;
+use kernel::common::VolatileCell;
 use kernel::support;
 use peripherals;
 use power;
+use pinmap::PinMap;
+
+const NVIC_ISPR0: usize = 0xE000_E200;
+
+/// Pend a peripheral interrupt from software. Its handler then runs from the
+/// kernel loop as if the peripheral had raised it, and has to tell the two apart.
+pub fn pend_interrupt(interrupt: u32) {
+    let ispr = unsafe { &*(NVIC_ISPR0 as *const VolatileCell<u32>) };
+    ispr.set(1 << interrupt);
+}
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -36,22 +53,25 @@ impl From<u32> for SleepMode {
 
 pub struct Cc26x0 {
     mpu: (),
-    systick: cortexm3::systick::SysTick,
+    systick: rtc::RtcSysTick,
+    // The pins are put into their low power state before deep sleep
+    board: &'static PinMap,
 }
 
 impl Cc26x0 {
-    pub unsafe fn new() -> Cc26x0 {
+    pub unsafe fn new(board: &'static PinMap) -> Cc26x0 {
         Cc26x0 {
             mpu: (),
-            // The systick clocks with 48MHz by default
-            systick: cortexm3::systick::SysTick::new_with_calibration(48 * 1000000),
+            // Timeslices are timed on the AON RTC, which survives sleep and clock switches
+            systick: rtc::RtcSysTick::new(),
+            board,
         }
     }
 }
 
 impl kernel::Chip for Cc26x0 {
     type MPU = ();
-    type SysTick = cortexm3::systick::SysTick;
+    type SysTick = rtc::RtcSysTick;
 
     fn mpu(&self) -> &Self::MPU {
         &self.mpu
@@ -63,26 +83,33 @@ impl kernel::Chip for Cc26x0 {
 
     fn service_pending_interrupts(&mut self) {
         unsafe {
+            // Called on every pass of the kernel main loop
+            watchdog::WATCHDOG.tickle();
+
             while let Some(interrupt) = nvic::next_pending() {
                 match interrupt {
                     GPIO => gpio::PORT.handle_interrupt(),
                     AON_RTC => rtc::RTC.handle_interrupt(),
 
                     UART0 => uart::UART0.handle_interrupt(),
+                    X0_SSI0 => ssi::SSI0.handle_interrupt(),
 
-                    GPT0A => timer::GPT0.handle_interrupt(),
-                    GPT0B => timer::GPT0.handle_interrupt(),
-                    GPT1A => timer::GPT1.handle_interrupt(),
-                    GPT1B => timer::GPT1.handle_interrupt(),
-                    GPT2A => timer::GPT2.handle_interrupt(),
-                    GPT2B => timer::GPT2.handle_interrupt(),
-                    GPT3A => timer::GPT3.handle_interrupt(),
-                    GPT3B => timer::GPT3.handle_interrupt(),
+                    GPT0A => timer::GPT0.handle_interrupt(timer::Half::A),
+                    GPT0B => timer::GPT0.handle_interrupt(timer::Half::B),
+                    GPT1A => timer::GPT1.handle_interrupt(timer::Half::A),
+                    GPT1B => timer::GPT1.handle_interrupt(timer::Half::B),
+                    GPT2A => timer::GPT2.handle_interrupt(timer::Half::A),
+                    GPT2B => timer::GPT2.handle_interrupt(timer::Half::B),
+                    GPT3A => timer::GPT3.handle_interrupt(timer::Half::A),
+                    GPT3B => timer::GPT3.handle_interrupt(timer::Half::B),
 
                     X0_RF_CMD_ACK => radio::RFC.handle_interrupt(radio::rfc::RfcInterrupt::CmdAck),
                     X0_RF_CPE0 => radio::RFC.handle_interrupt(radio::rfc::RfcInterrupt::Cpe0),
                     X0_RF_CPE1 => radio::RFC.handle_interrupt(radio::rfc::RfcInterrupt::Cpe1),
 
+                    X0_FLASH => flash::FLASH.handle_interrupt(),
+                    X0_WATCHDOG => watchdog::WATCHDOG.handle_interrupt(),
+
                     // AON Programmable interrupt
                     // We need to ignore JTAG events since some debuggers emit these
                     AON_PROG => (),
@@ -92,11 +119,16 @@ impl kernel::Chip for Cc26x0 {
                 n.clear_pending();
                 n.enable();
             }
+
+            // Edges that woke the chip from deep sleep
+            if gpio::PORT.has_pending_events() {
+                gpio::PORT.handle_interrupt();
+            }
         }
     }
 
     fn has_pending_interrupts(&self) -> bool {
-        unsafe { nvic::has_pending() }
+        unsafe { nvic::has_pending() || gpio::PORT.has_pending_events() }
     }
 
     fn sleep(&self) {
@@ -105,12 +137,16 @@ impl kernel::Chip for Cc26x0 {
         match sleep_mode {
             SleepMode::DeepSleep => unsafe {
                 peripherals::M.before_sleep(sleep_mode as u32);
-                power::prepare_deep_sleep();
+                power::prepare_deep_sleep(self.board);
             },
             _ => (),
         }
 
-        unsafe { support::wfi() }
+        unsafe {
+            watchdog::WATCHDOG.set_idle(true);
+            support::wfi();
+            watchdog::WATCHDOG.set_idle(false);
+        }
 
         match sleep_mode {
             SleepMode::DeepSleep => unsafe {
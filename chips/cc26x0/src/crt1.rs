@@ -1,5 +1,6 @@
 use cortexm3::{generic_isr, nvic, systick_handler, SVC_Handler};
 use setup;
+use watchdog;
 
 extern "C" {
     // Symbols defined in the linker file
@@ -30,7 +31,7 @@ unsafe extern "C" fn hard_fault_handler() {
 pub static BASE_VECTORS: [unsafe extern fn(); 50] = [
     _estack,
     reset_handler,
-    unhandled_interrupt, // NMI
+    watchdog::nmi_handler, // NMI
     hard_fault_handler, // Hard Fault
     unhandled_interrupt, // MPU fault
     unhandled_interrupt, // Bus fault
@@ -58,7 +59,7 @@ pub static BASE_VECTORS: [unsafe extern fn(); 50] = [
     generic_isr, // RF Core Command Acknowledge
     generic_isr, // I2S
     generic_isr, // AUX software event 1
-    generic_isr, // Watchdog timer
+    generic_isr, // Watchdog timer (when not routed to the NMI)
     generic_isr, // Timer 0 subtimer A
     generic_isr, // Timer 0 subtimer B
     generic_isr, // Timer 1 subtimer A
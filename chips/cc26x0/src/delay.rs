@@ -0,0 +1,86 @@
+//! Delays and timeouts
+//!
+//! Busy waits measured in real time on the AON RTC, which counts at 32.768kHz
+//! independent of the MCU clock, the optimisation level and the sleep modes. Meant
+//! for the short waits drivers need while talking to hardware; anything longer
+//! should use an alarm instead of blocking the kernel.
+//!
+//! The RTC advances in steps of ~30.5us, so waits are at least as long as asked
+//! for and at most one RTC period longer.
+//!
+//! ```rust,ignore
+//! // Wait at most 10ms for the peripheral to become ready
+//! if !delay::wait_for(10_000, || peripheral.ready()) {
+//!     return ReturnCode::FAIL;
+//! }
+//! ```
+
+use rtc;
+
+/// The current RTC time in 1/2^32 parts of a second.
+fn now() -> u64 {
+    let rtc = unsafe { &rtc::RTC };
+    if !rtc.is_enabled() {
+        // Time would stand still and every wait would hang
+        rtc.start();
+    }
+
+    let (sec, subsec) = rtc.read_time();
+    (sec as u64) << 32 | subsec as u64
+}
+
+fn us_to_rtc(us: u32) -> u64 {
+    ((us as u64) << 32) / 1_000_000
+}
+
+/// A point in time a bounded wait gives up at.
+#[derive(Copy, Clone)]
+pub struct Timeout {
+    deadline: u64,
+}
+
+impl Timeout {
+    /// A timeout expiring `us` microseconds from now.
+    pub fn us(us: u32) -> Timeout {
+        Timeout {
+            deadline: now() + us_to_rtc(us) + 1,
+        }
+    }
+
+    pub fn ms(ms: u32) -> Timeout {
+        Timeout {
+            deadline: now() + ((ms as u64) << 32) / 1_000 + 1,
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        now() >= self.deadline
+    }
+}
+
+/// Wait for at least `us` microseconds.
+pub fn busy_wait_us(us: u32) {
+    let timeout = Timeout::us(us);
+    while !timeout.expired() {}
+}
+
+/// Wait for at least `ms` milliseconds.
+pub fn busy_wait_ms(ms: u32) {
+    let timeout = Timeout::ms(ms);
+    while !timeout.expired() {}
+}
+
+/// Poll `condition` until it holds or `timeout_us` microseconds have passed.
+/// Returns whether the condition held.
+pub fn wait_for<F: FnMut() -> bool>(timeout_us: u32, mut condition: F) -> bool {
+    let timeout = Timeout::us(timeout_us);
+    loop {
+        if condition() {
+            return true;
+        }
+        if timeout.expired() {
+            // The condition may have become true while the time was read
+            return condition();
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//! External serial flash, SensorTag
+//!
+//! The SensorTag carries a serial NOR flash (W25X20 or MX25R, depending on the
+//! board revision) on SSI0. Both parts share the same basic command set, and the
+//! size of the part is taken from the capacity byte of its JEDEC ID.
+//!
+//! The flash is exposed through the flash HIL in 4KB sectors, which is the smallest
+//! erasable unit. Writing a page therefore erases the sector before it is
+//! programmed 256 bytes at a time.
+//!
+//! Append-only users, like a log, can't afford to erase a whole sector for every
+//! few bytes they add. `program` writes into erased flash without erasing it, and
+//! `erase_sector` erases a single sector, both reporting to the `RawClient`.
+//!
+//! Before the chip transitions into deep sleep the flash is sent into deep power-down,
+//! and it is released again on the next access.
+
+use core::cell::Cell;
+use core::ops::{Index, IndexMut};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster, SpiMasterClient};
+use kernel::ReturnCode;
+
+use chip::SleepMode;
+use peripheral_manager::PowerClient;
+use ssi;
+
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_READ_DATA: u8 = 0x03;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_READ_JEDEC_ID: u8 = 0x9F;
+const CMD_RELEASE_POWER_DOWN: u8 = 0xAB;
+const CMD_DEEP_POWER_DOWN: u8 = 0xB9;
+
+// Write in progress
+const STATUS_WIP: u8 = 0x01;
+
+pub const MANUFACTURER_WINBOND: u8 = 0xEF;
+pub const MANUFACTURER_MACRONIX: u8 = 0xC2;
+
+pub const SECTOR_SIZE: usize = 4096;
+const PROGRAM_PAGE_SIZE: usize = 256;
+// Command byte followed by a 24-bit address
+const HEADER_SIZE: usize = 4;
+pub const BUFFER_SIZE: usize = HEADER_SIZE + PROGRAM_PAGE_SIZE;
+
+// Number of JEDEC ID reads to wait for the flash to leave deep power-down
+const MAX_WAKEUP_POLLS: usize = 16;
+
+const FLASH_SPI_RATE: u32 = 4_000_000;
+
+pub static mut TX_BUF: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+pub static mut RX_BUF: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+pub static mut FLASH: ExtFlash = unsafe { ExtFlash::new(&ssi::SSI0) };
+
+pub struct ExtFlashPage(pub [u8; SECTOR_SIZE]);
+
+impl Default for ExtFlashPage {
+    fn default() -> Self {
+        ExtFlashPage([0; SECTOR_SIZE])
+    }
+}
+
+impl ExtFlashPage {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Index<usize> for ExtFlashPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for ExtFlashPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for ExtFlashPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// Client of the operations outside of the flash HIL.
+pub trait RawClient {
+    fn program_done(&self, buffer: &'static mut [u8], error: hil::flash::Error);
+    fn erase_done(&self, error: hil::flash::Error);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Read,
+    Write,
+    Erase,
+    // Through `program` and `erase_sector`
+    RawProgram,
+    RawErase,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ReadId { polls: usize },
+    WakeUp { polls: usize },
+    Read { offset: usize },
+    EraseWriteEnable,
+    Erase,
+    ProgramWriteEnable { offset: usize },
+    Program { offset: usize },
+}
+
+pub struct ExtFlash {
+    ssi: &'static ssi::SSI,
+    client: Cell<Option<&'static hil::flash::Client<ExtFlash>>>,
+    raw_client: Cell<Option<&'static RawClient>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    page: TakeCell<'static, ExtFlashPage>,
+    // Data, address and length of a raw program
+    data: TakeCell<'static, [u8]>,
+    address: Cell<usize>,
+    length: Cell<usize>,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    sector: Cell<usize>,
+    manufacturer: Cell<u8>,
+    size: Cell<usize>,
+    powered_down: Cell<bool>,
+}
+
+impl ExtFlash {
+    pub const fn new(ssi: &'static ssi::SSI) -> ExtFlash {
+        ExtFlash {
+            ssi,
+            client: Cell::new(None),
+            raw_client: Cell::new(None),
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            page: TakeCell::empty(),
+            data: TakeCell::empty(),
+            address: Cell::new(0),
+            length: Cell::new(0),
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::None),
+            sector: Cell::new(0),
+            manufacturer: Cell::new(0),
+            size: Cell::new(0),
+            powered_down: Cell::new(false),
+        }
+    }
+
+    /// Setup the SSI for the flash and identify the part through its JEDEC ID.
+    /// The SSI pins, chip select and client need to be set beforehand.
+    ///
+    /// A warm reset doesn't power cycle the flash, so it may still be in deep
+    /// power-down from before the reset and is woken up first.
+    pub fn initialize(&self, tx_buffer: &'static mut [u8], rx_buffer: &'static mut [u8]) {
+        self.tx_buffer.replace(tx_buffer);
+        self.rx_buffer.replace(rx_buffer);
+
+        self.ssi.init();
+        self.ssi.set_clock(ClockPolarity::IdleLow);
+        self.ssi.set_phase(ClockPhase::SampleLeading);
+        self.ssi.set_rate(FLASH_SPI_RATE);
+
+        self.state.set(State::ReadId { polls: 0 });
+        self.send(1, CMD_RELEASE_POWER_DOWN, 0);
+    }
+
+    /// The JEDEC manufacturer ID of the part, or 0 if it has not been identified.
+    pub fn manufacturer(&self) -> u8 {
+        self.manufacturer.get()
+    }
+
+    /// Size of the part in bytes, or 0 if it has not been identified.
+    pub fn size(&self) -> usize {
+        self.size.get()
+    }
+
+    pub fn number_of_sectors(&self) -> usize {
+        self.size.get() / SECTOR_SIZE
+    }
+
+    pub fn set_raw_client(&self, client: &'static RawClient) {
+        self.raw_client.set(Some(client));
+    }
+
+    /// Program the first `length` bytes of `buffer` at `address`, which have to be
+    /// erased. The range must not cross a 256 byte program page.
+    pub fn program(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        let res = self.check_sector(address / SECTOR_SIZE);
+        if res != ReturnCode::SUCCESS {
+            return Err((res, buffer));
+        }
+        if length == 0 || length > buffer.len()
+            || address % PROGRAM_PAGE_SIZE + length > PROGRAM_PAGE_SIZE
+        {
+            return Err((ReturnCode::EINVAL, buffer));
+        }
+
+        self.data.replace(buffer);
+        self.address.set(address);
+        self.length.set(length);
+        self.start_operation(Operation::RawProgram, address / SECTOR_SIZE);
+        Ok(())
+    }
+
+    /// Erase a single sector, without the page read and write of the flash HIL.
+    pub fn erase_sector(&self, sector: usize) -> ReturnCode {
+        let res = self.check_sector(sector);
+        if res == ReturnCode::SUCCESS {
+            self.start_operation(Operation::RawErase, sector);
+        }
+        res
+    }
+
+    /// Send a command with an address header, along with any data already in the
+    /// tx buffer following the header.
+    fn send(&self, len: usize, command: u8, address: usize) {
+        self.tx_buffer.take().map(|tx_buffer| {
+            tx_buffer[0] = command;
+            tx_buffer[1] = (address >> 16) as u8;
+            tx_buffer[2] = (address >> 8) as u8;
+            tx_buffer[3] = address as u8;
+
+            let rx_buffer = self.rx_buffer.take();
+            self.ssi.read_write_bytes(tx_buffer, rx_buffer, len);
+        });
+    }
+
+    fn sector_address(&self) -> usize {
+        self.sector.get() * SECTOR_SIZE
+    }
+
+    fn check_sector(&self, sector: usize) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            ReturnCode::EBUSY
+        } else if self.size.get() == 0 {
+            ReturnCode::ENODEVICE
+        } else if sector >= self.number_of_sectors() {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn start_operation(&self, operation: Operation, sector: usize) {
+        self.operation.set(operation);
+        self.sector.set(sector);
+
+        if self.powered_down.get() {
+            self.state.set(State::WakeUp { polls: 0 });
+            self.send(1, CMD_RELEASE_POWER_DOWN, 0);
+        } else {
+            self.continue_operation();
+        }
+    }
+
+    fn continue_operation(&self) {
+        match self.operation.get() {
+            Operation::Read => {
+                self.state.set(State::Read { offset: 0 });
+                self.send(BUFFER_SIZE, CMD_READ_DATA, self.sector_address());
+            }
+            Operation::Write | Operation::Erase | Operation::RawErase => {
+                self.state.set(State::EraseWriteEnable);
+                self.send(1, CMD_WRITE_ENABLE, 0);
+            }
+            Operation::RawProgram => {
+                self.state.set(State::ProgramWriteEnable { offset: 0 });
+                self.send(1, CMD_WRITE_ENABLE, 0);
+            }
+            Operation::None => self.state.set(State::Idle),
+        }
+    }
+
+    /// Returns true once a status read reports that the last program or erase
+    /// has finished, otherwise another status read is issued.
+    fn poll_done(&self) -> bool {
+        let done = self.tx_buffer
+            .map_or(false, |tx_buffer| tx_buffer[0] == CMD_READ_STATUS)
+            && self.rx_buffer
+                .map_or(false, |rx_buffer| rx_buffer[1] & STATUS_WIP == 0);
+
+        if !done {
+            self.send(2, CMD_READ_STATUS, 0);
+        }
+        done
+    }
+
+    /// Program the data of a raw program, after the write enable.
+    fn program_data(&self, offset: usize) {
+        let length = self.length.get();
+        let tx_buffer = self.tx_buffer.take();
+        self.data.map(|data| {
+            tx_buffer.map(|tx_buffer| {
+                tx_buffer[HEADER_SIZE..HEADER_SIZE + length].copy_from_slice(&data[..length]);
+                self.tx_buffer.replace(tx_buffer);
+            });
+        });
+
+        self.state.set(State::Program { offset });
+        self.send(HEADER_SIZE + length, CMD_PAGE_PROGRAM, self.address.get());
+    }
+
+    fn operation_complete(&self, error: hil::flash::Error) {
+        let operation = self.operation.get();
+        self.state.set(State::Idle);
+        self.operation.set(Operation::None);
+
+        match operation {
+            Operation::RawProgram => {
+                self.raw_client.get().map(move |client| {
+                    self.data
+                        .take()
+                        .map(move |data| client.program_done(data, error));
+                });
+                return;
+            }
+            Operation::RawErase => {
+                self.raw_client.get().map(move |client| client.erase_done(error));
+                return;
+            }
+            _ => (),
+        }
+
+        self.client.get().map(move |client| match operation {
+            Operation::Read => {
+                self.page
+                    .take()
+                    .map(move |page| client.read_complete(page, error));
+            }
+            Operation::Write => {
+                self.page
+                    .take()
+                    .map(move |page| client.write_complete(page, error));
+            }
+            Operation::Erase => client.erase_complete(error),
+            Operation::RawProgram | Operation::RawErase | Operation::None => (),
+        });
+    }
+}
+
+impl SpiMasterClient for ExtFlash {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+    ) {
+        self.tx_buffer.replace(write_buffer);
+        self.rx_buffer.put(read_buffer);
+
+        match self.state.get() {
+            State::Idle => (),
+            State::ReadId { polls } => {
+                // Like a wake up, the part answers the ID read once it is awake
+                let identified = polls > 0 && self.rx_buffer.map_or(false, |rx_buffer| {
+                    // The capacity is given as log2 of the size in bytes
+                    let capacity = rx_buffer[3] as usize;
+                    if rx_buffer[1] != 0x00 && rx_buffer[1] != 0xFF && capacity < 32 {
+                        self.manufacturer.set(rx_buffer[1]);
+                        self.size.set(1 << capacity);
+                        true
+                    } else {
+                        false
+                    }
+                });
+
+                if identified || polls >= MAX_WAKEUP_POLLS {
+                    self.state.set(State::Idle);
+                } else {
+                    self.state.set(State::ReadId { polls: polls + 1 });
+                    self.send(HEADER_SIZE, CMD_READ_JEDEC_ID, 0);
+                }
+            }
+            State::WakeUp { polls } => {
+                // The flash does not respond to the ID read until it is awake
+                let awake = polls > 0
+                    && self.rx_buffer.map_or(false, |rx_buffer| {
+                        rx_buffer[1] == self.manufacturer.get()
+                    });
+
+                if awake {
+                    self.powered_down.set(false);
+                    self.continue_operation();
+                } else if polls >= MAX_WAKEUP_POLLS {
+                    self.operation_complete(hil::flash::Error::FlashError);
+                } else {
+                    self.state.set(State::WakeUp { polls: polls + 1 });
+                    self.send(HEADER_SIZE, CMD_READ_JEDEC_ID, 0);
+                }
+            }
+            State::Read { offset } => {
+                let rx_buffer = self.rx_buffer.take();
+                self.page.map(|page| {
+                    rx_buffer.map(|rx_buffer| {
+                        let chunk = &rx_buffer[HEADER_SIZE..BUFFER_SIZE];
+                        for (i, c) in chunk.iter().enumerate() {
+                            page[offset + i] = *c;
+                        }
+                        self.rx_buffer.replace(rx_buffer);
+                    });
+                });
+
+                let offset = offset + PROGRAM_PAGE_SIZE;
+                if offset < SECTOR_SIZE {
+                    self.state.set(State::Read { offset });
+                    self.send(BUFFER_SIZE, CMD_READ_DATA, self.sector_address() + offset);
+                } else {
+                    self.operation_complete(hil::flash::Error::CommandComplete);
+                }
+            }
+            State::EraseWriteEnable => {
+                self.state.set(State::Erase);
+                self.send(HEADER_SIZE, CMD_SECTOR_ERASE, self.sector_address());
+            }
+            State::Erase => {
+                if self.poll_done() {
+                    if self.operation.get() == Operation::Write {
+                        self.state.set(State::ProgramWriteEnable { offset: 0 });
+                        self.send(1, CMD_WRITE_ENABLE, 0);
+                    } else {
+                        self.operation_complete(hil::flash::Error::CommandComplete);
+                    }
+                }
+            }
+            State::ProgramWriteEnable { offset } => {
+                if self.operation.get() == Operation::RawProgram {
+                    self.program_data(offset);
+                    return;
+                }
+
+                let tx_buffer = self.tx_buffer.take();
+                self.page.map(|page| {
+                    tx_buffer.map(|tx_buffer| {
+                        for i in 0..PROGRAM_PAGE_SIZE {
+                            tx_buffer[HEADER_SIZE + i] = page[offset + i];
+                        }
+                        self.tx_buffer.replace(tx_buffer);
+                    });
+                });
+
+                self.state.set(State::Program { offset });
+                self.send(BUFFER_SIZE, CMD_PAGE_PROGRAM, self.sector_address() + offset);
+            }
+            State::Program { offset } => {
+                if self.poll_done() {
+                    let offset = offset + PROGRAM_PAGE_SIZE;
+                    if offset < SECTOR_SIZE && self.operation.get() == Operation::Write {
+                        self.state.set(State::ProgramWriteEnable { offset });
+                        self.send(1, CMD_WRITE_ENABLE, 0);
+                    } else {
+                        self.operation_complete(hil::flash::Error::CommandComplete);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl hil::flash::Flash for ExtFlash {
+    type Page = ExtFlashPage;
+
+    fn read_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        let res = self.check_sector(page_number);
+        if res == ReturnCode::SUCCESS {
+            self.page.replace(buf);
+            self.start_operation(Operation::Read, page_number);
+        }
+        res
+    }
+
+    fn write_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        let res = self.check_sector(page_number);
+        if res == ReturnCode::SUCCESS {
+            self.page.replace(buf);
+            self.start_operation(Operation::Write, page_number);
+        }
+        res
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        let res = self.check_sector(page_number);
+        if res == ReturnCode::SUCCESS {
+            self.start_operation(Operation::Erase, page_number);
+        }
+        res
+    }
+}
+
+impl<C: hil::flash::Client<Self>> hil::flash::HasClient<'static, C> for ExtFlash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(Some(client));
+    }
+}
+
+impl PowerClient for ExtFlash {
+    fn before_sleep(&self, _sleep_mode: u32) {
+        if self.powered_down.get() || self.size.get() == 0 {
+            return;
+        }
+
+        // The flash draws several µA in standby, but less than 1µA in deep power-down
+        self.ssi.hold_low();
+        self.ssi.write_byte(CMD_DEEP_POWER_DOWN);
+        self.ssi.release_low();
+
+        self.powered_down.set(true);
+    }
+
+    fn after_wakeup(&self, _sleep_mode: u32) {
+        // The flash is kept in deep power-down until it is accessed again
+    }
+
+    fn lowest_sleep_mode(&self) -> u32 {
+        if self.state.get() == State::Idle {
+            SleepMode::DeepSleep as u32
+        } else {
+            SleepMode::Sleep as u32
+        }
+    }
+}
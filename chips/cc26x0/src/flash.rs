@@ -0,0 +1,341 @@
+//! Internal flash controller
+//!
+//! The on-chip flash is erased in 4KB sectors and programmed through the flash
+//! functions in the ROM driver table, which take care of the flash state machine
+//! and its timing parameters. The ROM functions block until the operation is
+//! done, so interrupts are masked for the duration (no code may execute from flash
+//! meanwhile) and the completion is delivered from the flash controller interrupt,
+//! which is pended in software once the operation has finished.
+//!
+//! Only the application region (PROG_ORIGIN, PROG_LENGTH) and the kernel storage
+//! region (STORAGE_ORIGIN, STORAGE_LENGTH) from the board's `layout.ld` are
+//! writable. Writes to the kernel image or the sector holding the CCFG are refused.
+//!
+//! For details see the flash and VIMS chapters in the cc2650 technical reference manual.
+
+use core::cell::Cell;
+use core::ops::{Index, IndexMut};
+use kernel::common::VolatileCell;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use kernel::support;
+use kernel::ReturnCode;
+
+pub const SECTOR_SIZE: usize = 4096;
+pub const FLASH_SIZE: usize = 128 * 1024;
+
+// "Flash controller" interrupt
+const FLASH_IRQ: u32 = 26;
+const NVIC_ISPR0: usize = 0xE000_E200;
+
+// The ROM API table, entry 0 is the ROM version and entry 10 the flash table
+const ROM_API_TABLE: usize = 0x1000_0180;
+const ROM_API_FLASH_TABLE_INDEX: usize = 10;
+const ROM_FLASH_SECTOR_ERASE: usize = 5;
+const ROM_FLASH_PROGRAM: usize = 6;
+
+// Returned by the ROM flash functions
+const FAPI_STATUS_SUCCESS: u32 = 0x0;
+
+const VIMS_BASE: usize = 0x4003_4000;
+const VIMS_MODE_GPRAM: u32 = 0x0;
+const VIMS_MODE_MASK: u32 = 0x3;
+const VIMS_STAT_MODE_CHANGING: u32 = 1 << 3;
+
+extern "C" {
+    // Symbols defined in the board's linker file
+    static PROG_ORIGIN: u8;
+    static PROG_LENGTH: u8;
+    static STORAGE_ORIGIN: u8;
+    static STORAGE_LENGTH: u8;
+    static CCFG_ORIGIN: u8;
+}
+
+/// Address of a linker script symbol, which holds the value of the symbol.
+fn linker_value(symbol: &'static u8) -> usize {
+    symbol as *const u8 as usize
+}
+
+pub struct FlashPage(pub [u8; SECTOR_SIZE]);
+
+impl Default for FlashPage {
+    fn default() -> Self {
+        FlashPage([0; SECTOR_SIZE])
+    }
+}
+
+impl FlashPage {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Index<usize> for FlashPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for FlashPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for FlashPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Read,
+    Write,
+    Erase,
+}
+
+#[repr(C)]
+struct VimsRegisters {
+    stat: VolatileCell<u32>,
+    ctl: VolatileCell<u32>,
+}
+
+type SectorEraseFn = unsafe extern "C" fn(u32) -> u32;
+type ProgramFn = unsafe extern "C" fn(*mut u8, u32, u32) -> u32;
+
+pub static mut FLASH: Flash = Flash::new();
+
+pub struct Flash {
+    vims: *const VimsRegisters,
+    client: Cell<Option<&'static hil::flash::Client<Flash>>>,
+    page: TakeCell<'static, FlashPage>,
+    operation: Cell<Operation>,
+    error: Cell<bool>,
+}
+
+impl Flash {
+    const fn new() -> Flash {
+        Flash {
+            vims: VIMS_BASE as *const VimsRegisters,
+            client: Cell::new(None),
+            page: TakeCell::empty(),
+            operation: Cell::new(Operation::None),
+            error: Cell::new(false),
+        }
+    }
+
+    pub fn number_of_sectors(&self) -> usize {
+        FLASH_SIZE / SECTOR_SIZE
+    }
+
+    /// The region holding the application images, as `(address, length)`.
+    pub fn app_region(&self) -> (usize, usize) {
+        unsafe { (linker_value(&PROG_ORIGIN), linker_value(&PROG_LENGTH)) }
+    }
+
+    /// The region reserved for kernel storage, as `(address, length)`.
+    pub fn storage_region(&self) -> (usize, usize) {
+        unsafe { (linker_value(&STORAGE_ORIGIN), linker_value(&STORAGE_LENGTH)) }
+    }
+
+    /// Whether the sector can be erased and programmed.
+    pub fn is_writable(&self, sector: usize) -> bool {
+        let (prog_start, prog_len, storage_start, storage_len, ccfg) = unsafe {
+            (
+                linker_value(&PROG_ORIGIN),
+                linker_value(&PROG_LENGTH),
+                linker_value(&STORAGE_ORIGIN),
+                linker_value(&STORAGE_LENGTH),
+                linker_value(&CCFG_ORIGIN),
+            )
+        };
+
+        let start = sector * SECTOR_SIZE;
+        let end = start + SECTOR_SIZE;
+        let within = |region_start: usize, region_len: usize| {
+            start >= region_start && end <= region_start + region_len
+        };
+
+        // The CCFG check is redundant with a correct layout, but a broken layout
+        // must never be able to brick the chip.
+        let ccfg_sector = ccfg / SECTOR_SIZE;
+        sector != ccfg_sector
+            && (within(prog_start, prog_len) || within(storage_start, storage_len))
+    }
+
+    pub fn handle_interrupt(&self) {
+        let operation = self.operation.get();
+        if operation == Operation::None {
+            return;
+        }
+        self.operation.set(Operation::None);
+
+        let error = if self.error.get() {
+            hil::flash::Error::FlashError
+        } else {
+            hil::flash::Error::CommandComplete
+        };
+
+        self.client.get().map(|client| match operation {
+            Operation::Read => {
+                self.page
+                    .take()
+                    .map(|page| client.read_complete(page, error));
+            }
+            Operation::Write => {
+                self.page
+                    .take()
+                    .map(|page| client.write_complete(page, error));
+            }
+            Operation::Erase => client.erase_complete(error),
+            Operation::None => (),
+        });
+    }
+
+    fn check_sector(&self, sector: usize, write: bool) -> ReturnCode {
+        if self.operation.get() != Operation::None {
+            ReturnCode::EBUSY
+        } else if sector >= self.number_of_sectors() {
+            ReturnCode::EINVAL
+        } else if write && !self.is_writable(sector) {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Run a blocking ROM flash operation with interrupts masked and the cache
+    /// disabled, so nothing is fetched from the flash while it is busy.
+    fn rom_operation<F: FnOnce() -> u32>(&self, f: F) -> bool {
+        let vims = unsafe { &*self.vims };
+        unsafe {
+            support::atomic(|| {
+                let ctl = vims.ctl.get();
+                vims.ctl.set((ctl & !VIMS_MODE_MASK) | VIMS_MODE_GPRAM);
+                while vims.stat.get() & VIMS_STAT_MODE_CHANGING != 0 {}
+
+                let status = f();
+
+                // Switching the mode invalidates the cache, so no stale data survives
+                vims.ctl.set(ctl);
+                while vims.stat.get() & VIMS_STAT_MODE_CHANGING != 0 {}
+
+                status == FAPI_STATUS_SUCCESS
+            })
+        }
+    }
+
+    fn erase_sector(&self, sector: usize) -> bool {
+        self.rom_operation(|| unsafe {
+            let erase: SectorEraseFn = rom_flash_function(ROM_FLASH_SECTOR_ERASE);
+            erase((sector * SECTOR_SIZE) as u32)
+        })
+    }
+
+    fn program_bytes(&self, address: usize, data: &[u8]) -> bool {
+        self.rom_operation(|| unsafe {
+            let program: ProgramFn = rom_flash_function(ROM_FLASH_PROGRAM);
+            program(data.as_ptr() as *mut u8, address as u32, data.len() as u32)
+        })
+    }
+
+    /// Erase a sector and wait for it to complete. Meant for code which can't wait
+    /// for the flash interrupt, like the serial loader running before the kernel.
+    pub fn erase(&self, sector: usize) -> ReturnCode {
+        let res = self.check_sector(sector, true);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        if self.erase_sector(sector) {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        }
+    }
+
+    /// Program previously erased bytes and wait for it to complete.
+    pub fn program(&self, address: usize, data: &[u8]) -> ReturnCode {
+        if data.is_empty() {
+            return ReturnCode::SUCCESS;
+        }
+
+        let first = address / SECTOR_SIZE;
+        let last = (address + data.len() - 1) / SECTOR_SIZE;
+        for sector in first..last + 1 {
+            let res = self.check_sector(sector, true);
+            if res != ReturnCode::SUCCESS {
+                return res;
+            }
+        }
+
+        if self.program_bytes(address, data) {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        }
+    }
+
+    fn complete(&self, operation: Operation, success: bool) {
+        self.operation.set(operation);
+        self.error.set(!success);
+
+        // Deliver the completion from the interrupt handler rather than from
+        // within the call that started the operation.
+        let ispr = unsafe { &*(NVIC_ISPR0 as *const VolatileCell<u32>) };
+        ispr.set(1 << FLASH_IRQ);
+    }
+}
+
+unsafe fn rom_flash_function<T: Copy>(index: usize) -> T {
+    let tables = ROM_API_TABLE as *const *const T;
+    let flash_table = *tables.offset(ROM_API_FLASH_TABLE_INDEX as isize);
+    *flash_table.offset(index as isize)
+}
+
+impl hil::flash::Flash for Flash {
+    type Page = FlashPage;
+
+    fn read_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        let res = self.check_sector(page_number, false);
+        if res == ReturnCode::SUCCESS {
+            let src = (page_number * SECTOR_SIZE) as *const u8;
+            for (i, byte) in buf.0.iter_mut().enumerate() {
+                *byte = unsafe { *src.offset(i as isize) };
+            }
+            self.page.replace(buf);
+            self.complete(Operation::Read, true);
+        }
+        res
+    }
+
+    fn write_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        let res = self.check_sector(page_number, true);
+        if res == ReturnCode::SUCCESS {
+            let address = page_number * SECTOR_SIZE;
+            let success = self.erase_sector(page_number) && self.program_bytes(address, &buf.0);
+            self.page.replace(buf);
+            self.complete(Operation::Write, success);
+        }
+        res
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        let res = self.check_sector(page_number, true);
+        if res == ReturnCode::SUCCESS {
+            let success = self.erase_sector(page_number);
+            self.complete(Operation::Erase, success);
+        }
+        res
+    }
+}
+
+impl<C: hil::flash::Client<Self>> hil::flash::HasClient<'static, C> for Flash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(Some(client));
+    }
+}
@@ -11,6 +11,8 @@ use kernel::hil::gpio::Pin;
 use kernel::hil;
 use prcm;
 use ioc;
+use pinmap::PinMap;
+use pins;
 
 const NUM_PINS: usize = 32;
 const GPIO_BASE: *const GpioRegisters = 0x4002_2000 as *const GpioRegisters;
@@ -34,7 +36,7 @@ pub struct GpioRegisters {
 }
 
 use kernel::hil::gpio::PinCtl;
-pub unsafe fn power_on_gpio() {
+pub unsafe fn power_on_gpio(board: &PinMap) {
     // Power on peripherals (eg. GPIO)
     prcm::Power::enable_domain(prcm::PowerDomain::Peripherals);
     // Wait for it to turn on until we continue
@@ -48,65 +50,51 @@ pub unsafe fn power_on_gpio() {
     }
 
     // Set all pins in a low-power state
-    set_pins_to_default_conf();
+    set_pins_to_default_conf(board);
 }
 
-pub unsafe fn set_pins_to_default_conf() {
-    const MIC_POWER: usize = 13;
-    const AUDIO_DI: usize = 2;
-    const AUDIO_CLK: usize = 11;
-    const DP2: usize = 23;
-    const DP1: usize = 24;
-    const DP0: usize = 25;
-    const DP3: usize = 27;
-    const DEVPK_ID: usize = 30;
-    const UART_TX: usize = 29;
-    const SDA_HP: usize = 8;
-    const SCL_HP: usize = 9;
-    const SDA: usize = 5;
-    const SCL: usize = 6;
-    const SPI_MOSI: usize = 19;
-    const SPI_CLK_FLASH: usize = 17;
-    const TMP_RDY: usize = 1;
-
-    PORT[TMP_RDY].make_input();
-    PORT[TMP_RDY].set_input_mode(hil::gpio::InputMode::PullUp);
-
-    PORT[SPI_MOSI].make_input();
-    PORT[SPI_MOSI].set_input_mode(hil::gpio::InputMode::PullDown);
-    PORT[SPI_CLK_FLASH].make_input();
-    PORT[SPI_CLK_FLASH].set_input_mode(hil::gpio::InputMode::PullDown);
-
-    PORT[SDA_HP].make_input();
-    PORT[SDA_HP].set_input_mode(hil::gpio::InputMode::PullDown);
-    PORT[SCL_HP].make_input();
-    PORT[SCL_HP].set_input_mode(hil::gpio::InputMode::PullDown);
-    PORT[SDA].make_input();
-    PORT[SDA].set_input_mode(hil::gpio::InputMode::PullUp);
-    PORT[SCL].make_input();
-    PORT[SCL].set_input_mode(hil::gpio::InputMode::PullUp);
-
-    PORT[DP0].make_input();
-    PORT[DP0].set_input_mode(hil::gpio::InputMode::PullDown);
-    PORT[DP1].make_input();
-    PORT[DP1].set_input_mode(hil::gpio::InputMode::PullDown);
-    PORT[DP2].make_input();
-    PORT[DP2].set_input_mode(hil::gpio::InputMode::PullDown);
-    PORT[DP3].make_input();
-    PORT[DP3].set_input_mode(hil::gpio::InputMode::PullDown);
-
-    PORT[DEVPK_ID].make_input();
-    PORT[DEVPK_ID].set_input_mode(hil::gpio::InputMode::PullUp);
-
-    PORT[MIC_POWER].make_output();
-    PORT[MIC_POWER].clear();
-    PORT[AUDIO_DI].make_input();
-    PORT[AUDIO_DI].set_input_mode(hil::gpio::InputMode::PullDown);
-    PORT[AUDIO_CLK].make_input();
-    PORT[AUDIO_CLK].set_input_mode(hil::gpio::InputMode::PullDown);
-
-    PORT[UART_TX].make_input();
-    PORT[UART_TX].set_input_mode(hil::gpio::InputMode::PullDown);
+pub unsafe fn set_pins_to_default_conf(board: &PinMap) {
+    PORT[board.tmp_rdy].make_input();
+    PORT[board.tmp_rdy].set_input_mode(hil::gpio::InputMode::PullUp);
+
+    PORT[board.spi_mosi].make_input();
+    PORT[board.spi_mosi].set_input_mode(hil::gpio::InputMode::PullDown);
+    PORT[board.spi_clk].make_input();
+    PORT[board.spi_clk].set_input_mode(hil::gpio::InputMode::PullDown);
+    // Keep the external flash deselected
+    PORT[board.flash_cs].make_output();
+    PORT[board.flash_cs].set();
+
+    PORT[board.i2c_sda_hp].make_input();
+    PORT[board.i2c_sda_hp].set_input_mode(hil::gpio::InputMode::PullDown);
+    PORT[board.i2c_scl_hp].make_input();
+    PORT[board.i2c_scl_hp].set_input_mode(hil::gpio::InputMode::PullDown);
+    PORT[board.i2c_sda].make_input();
+    PORT[board.i2c_sda].set_input_mode(hil::gpio::InputMode::PullUp);
+    PORT[board.i2c_scl].make_input();
+    PORT[board.i2c_scl].set_input_mode(hil::gpio::InputMode::PullUp);
+
+    PORT[board.dp0].make_input();
+    PORT[board.dp0].set_input_mode(hil::gpio::InputMode::PullDown);
+    PORT[board.dp1].make_input();
+    PORT[board.dp1].set_input_mode(hil::gpio::InputMode::PullDown);
+    PORT[board.dp2].make_input();
+    PORT[board.dp2].set_input_mode(hil::gpio::InputMode::PullDown);
+    PORT[board.dp3].make_input();
+    PORT[board.dp3].set_input_mode(hil::gpio::InputMode::PullDown);
+
+    PORT[board.devpack_id].make_input();
+    PORT[board.devpack_id].set_input_mode(hil::gpio::InputMode::PullUp);
+
+    PORT[board.mic_power].make_output();
+    PORT[board.mic_power].clear();
+    PORT[board.audio_di].make_input();
+    PORT[board.audio_di].set_input_mode(hil::gpio::InputMode::PullDown);
+    PORT[board.audio_clk].make_input();
+    PORT[board.audio_clk].set_input_mode(hil::gpio::InputMode::PullDown);
+
+    PORT[board.uart_tx].make_input();
+    PORT[board.uart_tx].set_input_mode(hil::gpio::InputMode::PullDown);
 }
 
 pub struct GPIOPin {
@@ -145,6 +133,11 @@ impl GPIOPin {
     pub fn iocfg(&self) -> &ioc::IocfgPin {
         &ioc::IOCFG[self.pin]
     }
+
+    /// Claim the pin for `owner`, see `pins::claim`.
+    pub fn claim(&self, owner: &'static str) {
+        pins::claim(self.pin, owner);
+    }
 }
 
 impl hil::gpio::PinCtl for GPIOPin {
@@ -199,11 +192,20 @@ impl hil::gpio::Pin for GPIOPin {
 
     fn enable_interrupt(&self, client_data: usize, mode: hil::gpio::InterruptMode) {
         self.client_data.set(client_data);
+        // The edge itself wakes the chip from deep sleep through the AON IO event,
+        // the level additionally wakes it from shutdown
+        let wakeup = match mode {
+            hil::gpio::InterruptMode::FallingEdge => ioc::Wakeup::OnLow,
+            hil::gpio::InterruptMode::RisingEdge => ioc::Wakeup::OnHigh,
+            hil::gpio::InterruptMode::EitherEdge => ioc::Wakeup::None,
+        };
         ioc::IOCFG[self.pin].enable_interrupt(mode);
+        ioc::IOCFG[self.pin].set_wakeup(wakeup);
     }
 
     fn disable_interrupt(&self) {
         ioc::IOCFG[self.pin].disable_interrupt();
+        ioc::IOCFG[self.pin].set_wakeup(ioc::Wakeup::None);
     }
 }
 
@@ -234,16 +236,49 @@ impl Port {
 
         // evflags indicate which pins has triggered an interrupt,
         // we need to call the respective handler for positive bit in evflags.
-        let mut pin: usize = usize::max_value();
-        while pin < self.pins.len() {
-            pin = evflags.trailing_zeros() as usize;
-            if pin >= self.pins.len() {
-                break;
-            }
+        let mut pending = evflags;
+        while pending != 0 {
+            let pin = pending.trailing_zeros() as usize;
+            pending &= !(1 << pin);
 
             self.pins[pin].handle_interrupt();
         }
     }
+
+    /// Drive the pins in `mask` high, in one register write.
+    pub fn set_pins(&self, mask: u32) {
+        let regs: &GpioRegisters = unsafe { &*GPIO_BASE };
+        regs.dout_set.set(mask);
+    }
+
+    /// Drive the pins in `mask` low, in one register write.
+    pub fn clear_pins(&self, mask: u32) {
+        let regs: &GpioRegisters = unsafe { &*GPIO_BASE };
+        regs.dout_clr.set(mask);
+    }
+
+    /// Invert the output of the pins in `mask`, in one register write.
+    pub fn toggle_pins(&self, mask: u32) {
+        let regs: &GpioRegisters = unsafe { &*GPIO_BASE };
+        regs.dout_tgl.set(mask);
+    }
+
+    /// The input levels of all pins, bit n for DIO n.
+    pub fn read_pins(&self) -> u32 {
+        let regs: &GpioRegisters = unsafe { &*GPIO_BASE };
+        regs.din.get()
+    }
+
+    /// Whether an edge is flagged but not handled yet.
+    ///
+    /// The edge detection keeps running in deep sleep, where the edge wakes the
+    /// chip through the AON IO event. The flag is all that's left of it once the
+    /// peripheral domain is powered again, so the kernel checks for it after
+    /// waking up instead of relying on the NVIC.
+    pub fn has_pending_events(&self) -> bool {
+        let regs: &GpioRegisters = unsafe { &*GPIO_BASE };
+        regs.evflags.get() != 0
+    }
 }
 
 pub static mut PORT: Port = Port {
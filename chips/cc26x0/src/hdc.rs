@@ -1,5 +1,6 @@
 use i2c::I2cInterface;
 use core::cell::Cell;
+use delay;
 use sensor::Sensor;
 use kernel;
 
@@ -7,6 +8,8 @@ pub const HDC_TEMP_REG: u32 = 0x00;
 pub const HDC_CONF_REG: u32 = 0x02;
 
 pub const HDC_CONFIG: u32 = 0x1000; // 14 bit resolution
+// A 14 bit temperature conversion takes 6.35ms
+pub const HDC_CONVERSION_TIME_MS: u32 = 7;
 
 pub const HDC_INTERFACE: I2cInterface = I2cInterface::Interface0;
 pub const HDC_ADDRESS: u8 = 0x43;
@@ -39,9 +42,7 @@ impl HDC {
         self.sensor.get().write_reg_address(HDC_TEMP_REG as u8);
 
         // Delay to make sure the value is ready when reading
-        for _ in 0..0xFFFFFF {
-            asm!("NOP");
-        }
+        delay::busy_wait_ms(HDC_CONVERSION_TIME_MS);
 
         // Read the temperature
         self.sensor.get().read(&mut buf, 2);
@@ -1,8 +1,11 @@
 use prcm;
 use ioc;
+use pinmap::PinMap;
+use pins;
 use cc26xx::gpio;
 use kernel::hil::gpio::Pin;
 use core::cell::Cell;
+use delay;
 use kernel::common::VolatileCell;
 
 pub const I2C_MCR_MFE: u32 = 0x10;
@@ -26,13 +29,11 @@ pub const I2C_MSTAT_ADRACK_N: u32 = 0x4;
 pub const I2C_MSTAT_DATACK_N_M: u32 = 0x8;
 pub const I2C_MSTAT_ADRACK_N_M: u32 = 0x4;
 
-pub const BOARD_IO_SDA: usize = 0x5;
-pub const BOARD_IO_SCL: usize = 0x6;
-pub const BOARD_IO_SDA_HP: usize = 0x8;
-pub const BOARD_IO_SCL_HP: usize = 0x9;
-
 pub const MCU_CLOCK: u32 = 48_000_000;
 
+// Longest wait for the master, a byte takes 90us at 100kHz
+const BUSY_TIMEOUT_US: u32 = 10_000;
+
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum I2cInterface {
     Interface0 = 0,
@@ -71,6 +72,7 @@ pub struct I2C {
     regs: *mut Registers,
     slave_addr: Cell<u8>,
     interface: Cell<u8>,
+    board: Cell<Option<&'static PinMap>>,
 }
 
 impl I2C {
@@ -79,6 +81,7 @@ impl I2C {
             regs: I2C_BASE as *mut Registers,
             slave_addr: Cell::new(0),
             interface: Cell::new(I2cInterface::NoInterface as u8),
+            board: Cell::new(None),
         }
     }
 
@@ -90,6 +93,16 @@ impl I2C {
         self.configure(true);
     }
 
+    /// Claim the pins of both interfaces on `board`, the bus switches between them
+    /// at runtime. Interfaces can only be selected after this.
+    pub fn claim_pins(&self, board: &'static PinMap) {
+        self.board.set(Some(board));
+        pins::claim(board.i2c_sda, "I2C SDA");
+        pins::claim(board.i2c_scl, "I2C SCL");
+        pins::claim(board.i2c_sda_hp, "I2C SDA (HP)");
+        pins::claim(board.i2c_scl_hp, "I2C SCL (HP)");
+    }
+
     #[allow(unused)]
     pub fn shutdown(&self) {
         // Not implemented
@@ -288,24 +301,12 @@ impl I2C {
 
     // Limited busy wait for the master
     fn busy_wait_master(&self) -> bool {
-        let delay = 0xFFFFFF;
-        for _ in 0..delay {
-            if !self.master_busy() {
-                return true;
-            }
-        }
-        false
+        delay::wait_for(BUSY_TIMEOUT_US, || !self.master_busy())
     }
 
     // Limited busy wait for the master bus
     fn busy_wait_master_bus(&self) -> bool {
-        let delay = 0xFFFFFF;
-        for _ in 0..delay {
-            if !self.master_bus_busy() {
-                return true;
-            }
-        }
-        false
+        delay::wait_for(BUSY_TIMEOUT_US, || !self.master_bus_busy())
     }
 
     fn master_control(&self, cmd: u32) {
@@ -364,24 +365,37 @@ impl I2C {
             self.interface.set(interface);
 
             self.master_disable();
+            self.board
+                .get()
+                .map(|board| self.configure_pins(board, interface));
+            self.configure(true);
+        }
+    }
 
-            if interface == I2cInterface::Interface0 as u8 {
-                unsafe {
-                    ioc::IOCFG[BOARD_IO_SDA].enable_i2c_sda();
-                    ioc::IOCFG[BOARD_IO_SCL].enable_i2c_scl();
-                    gpio::PORT[BOARD_IO_SDA_HP].make_input();
-                    gpio::PORT[BOARD_IO_SCL_HP].make_input();
-                }
-            } else if interface == I2cInterface::Interface1 as u8 {
-                unsafe {
-                    ioc::IOCFG[BOARD_IO_SDA_HP].enable_i2c_sda();
-                    ioc::IOCFG[BOARD_IO_SCL_HP].enable_i2c_scl();
-                    gpio::PORT[BOARD_IO_SDA].make_input();
-                    gpio::PORT[BOARD_IO_SCL].make_input();
-                }
+    /// Route the bus to the pins of `interface`, the pins of the other one become inputs.
+    fn configure_pins(&self, board: &PinMap, interface: u8) {
+        if interface == I2cInterface::Interface0 as u8 {
+            unsafe {
+                ioc::IOCFG[board.i2c_sda].configure(i2c_pin(ioc::PortId::I2cSda));
+                ioc::IOCFG[board.i2c_scl].configure(i2c_pin(ioc::PortId::I2cScl));
+                gpio::PORT[board.i2c_sda_hp].make_input();
+                gpio::PORT[board.i2c_scl_hp].make_input();
+            }
+        } else if interface == I2cInterface::Interface1 as u8 {
+            unsafe {
+                ioc::IOCFG[board.i2c_sda_hp].configure(i2c_pin(ioc::PortId::I2cSda));
+                ioc::IOCFG[board.i2c_scl_hp].configure(i2c_pin(ioc::PortId::I2cScl));
+                gpio::PORT[board.i2c_sda].make_input();
+                gpio::PORT[board.i2c_scl].make_input();
             }
-
-            self.configure(true);
         }
     }
 }
+
+/// Open-drain with the internal pull-up, the bus also has external pull-ups.
+fn i2c_pin(port: ioc::PortId) -> ioc::Config {
+    ioc::Config::new(port)
+        .io_mode(ioc::IoMode::OpenDrain)
+        .pull(ioc::Pull::Up)
+        .input()
+}
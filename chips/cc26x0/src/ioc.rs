@@ -1,41 +1,282 @@
 //! IO Configuration (IOC)
 //!
-//! For details see p. 984 in the cc2650 technical reference manual.
-//!
 //! Required to setup and configure IO pins to different modes - all depending on
 //! usage (eg. UART, GPIO, etc). It is used internally.
+//!
+//! A peripheral takes over a pin by writing a complete `Config` to it, which
+//! names the signal the pin is routed to and the electrical configuration:
+//!
+//! ```rust,ignore
+//! ioc::IOCFG[pin].configure(
+//!     ioc::Config::new(ioc::PortId::I2cSda)
+//!         .io_mode(ioc::IoMode::OpenDrain)
+//!         .pull(ioc::Pull::Up)
+//!         .input(),
+//! );
+//! ```
+//!
+//! The GPIO module instead changes single fields of a pin as the application
+//! asks for them.
+//!
+//! For details see the I/O control chapter in the cc2650 technical reference
+//! manual.
 
-use kernel::common::regs::ReadWrite;
+use kernel::common::regs::{FieldValue, ReadWrite};
 use kernel::hil;
 
-pub const IOC_PULL_CTL: u8 = 13;
-pub const IOC_IE: u8 = 29;
-pub const IOC_EDGE_DET: u8 = 16;
-pub const IOC_EDGE_IRQ_EN: u8 = 18;
-
-pub const IOC_UART0_RX_ID: u32 = 0xF;
-pub const IOC_UART0_TX_ID: u32 = 0x10;
-pub const IOC_I2C_MSSDA: u32 = 0xD;
-pub const IOC_I2C_MSSCL: u32 = 0xE;
+/// The signal a pin is routed to.
+#[derive(Copy, Clone, PartialEq)]
+pub enum PortId {
+    Gpio,
+    AonClk32k,
+    AuxIo,
+    Ssi0Rx,
+    Ssi0Tx,
+    Ssi0Fss,
+    Ssi0Clk,
+    I2cSda,
+    I2cScl,
+    Uart0Rx,
+    Uart0Tx,
+    Uart0Cts,
+    Uart0Rts,
+    // MCU port events 0-7, connected to GPT0A, GPT0B, GPT1A, ... GPT3B
+    PortEvent(u8),
+    CpuSwv,
+    Ssi1Rx,
+    Ssi1Tx,
+    Ssi1Fss,
+    Ssi1Clk,
+    I2sAd0,
+    I2sAd1,
+    I2sWclk,
+    I2sBclk,
+    I2sMclk,
+    RfcTrc,
+    RfcGpo(u8),
+    RfcGpi(u8),
+}
 
-pub const IOC_IOMODE_OPEN_DRAIN_NORMAL: u32 = 0x4000000;
-pub const IOC_HYST_ENABLE: u32 = 0x40000000;
-pub const IOC_IOPULL_UP: u32 = 0x4000;
+impl PortId {
+    fn id(self) -> u32 {
+        match self {
+            PortId::Gpio => 0x00,
+            PortId::AonClk32k => 0x07,
+            PortId::AuxIo => 0x08,
+            PortId::Ssi0Rx => 0x09,
+            PortId::Ssi0Tx => 0x0A,
+            PortId::Ssi0Fss => 0x0B,
+            PortId::Ssi0Clk => 0x0C,
+            PortId::I2cSda => 0x0D,
+            PortId::I2cScl => 0x0E,
+            PortId::Uart0Rx => 0x0F,
+            PortId::Uart0Tx => 0x10,
+            PortId::Uart0Cts => 0x11,
+            PortId::Uart0Rts => 0x12,
+            PortId::PortEvent(event) => 0x17 + (event & 0x7) as u32,
+            PortId::CpuSwv => 0x20,
+            PortId::Ssi1Rx => 0x21,
+            PortId::Ssi1Tx => 0x22,
+            PortId::Ssi1Fss => 0x23,
+            PortId::Ssi1Clk => 0x24,
+            PortId::I2sAd0 => 0x25,
+            PortId::I2sAd1 => 0x26,
+            PortId::I2sWclk => 0x27,
+            PortId::I2sBclk => 0x28,
+            PortId::I2sMclk => 0x29,
+            PortId::RfcTrc => 0x2E,
+            PortId::RfcGpo(gpo) => 0x2F + (gpo & 0x3) as u32,
+            PortId::RfcGpi(gpi) => 0x33 + (gpi & 0x1) as u32,
+        }
+    }
+}
 
-pub const IOC_CURRENT_MODE: u32 = 0xC00;
-pub const IOC_DRIVE_STRENGTH: u32 = 0x300;
+/// How the pin drives its output.
+#[derive(Copy, Clone, PartialEq)]
+pub enum IoMode {
+    Normal,
+    Inverted,
+    // Only drives low, high is left to a pull-up
+    OpenDrain,
+    OpenDrainInverted,
+    // Only drives high, low is left to a pull-down
+    OpenSource,
+    OpenSourceInverted,
+}
 
+#[derive(Copy, Clone, PartialEq)]
 pub enum CurrentMode {
-    Current2mA = 0x0,
-    Current4mA = 0x400,
-    Current8mA = 0x800,
+    Current2mA,
+    Current4mA,
+    // 8mA on the high drive pins (DIO 5 to 8), 4mA on the others
+    Current8mA,
 }
 
+#[derive(Copy, Clone, PartialEq)]
 pub enum DriveStrength {
-    Auto = 0x0,
-    Max = 0x300,
-    Med = 0x200,
-    Min = 0x100,
+    // Adjusted to the supply voltage by the AON
+    Auto,
+    Min,
+    Med,
+    Max,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Wake the chip from shutdown on the level of the pin.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Wakeup {
+    None,
+    OnLow,
+    OnHigh,
+}
+
+/// Edges flagged in the GPIO event register (and, if enabled, interrupting).
+#[derive(Copy, Clone, PartialEq)]
+pub enum Edge {
+    None,
+    Falling,
+    Rising,
+    Both,
+}
+
+/// A complete pin configuration, see the module documentation.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Config {
+    port: PortId,
+    io_mode: IoMode,
+    slew_reduction: bool,
+    current: CurrentMode,
+    strength: DriveStrength,
+    pull: Pull,
+    hysteresis: bool,
+    wakeup: Wakeup,
+    edge: Edge,
+    edge_interrupt: bool,
+    input: bool,
+}
+
+impl Config {
+    /// The reset configuration with the pin routed to `port`: normal output at 2mA
+    /// with automatic drive strength, input buffer off, no pull and no events.
+    pub fn new(port: PortId) -> Config {
+        Config {
+            port,
+            io_mode: IoMode::Normal,
+            slew_reduction: false,
+            current: CurrentMode::Current2mA,
+            strength: DriveStrength::Auto,
+            pull: Pull::None,
+            hysteresis: false,
+            wakeup: Wakeup::None,
+            edge: Edge::None,
+            edge_interrupt: false,
+            input: false,
+        }
+    }
+
+    pub fn io_mode(mut self, io_mode: IoMode) -> Config {
+        self.io_mode = io_mode;
+        self
+    }
+
+    pub fn slew_reduction(mut self) -> Config {
+        self.slew_reduction = true;
+        self
+    }
+
+    pub fn drive(mut self, current: CurrentMode, strength: DriveStrength) -> Config {
+        self.current = current;
+        self.strength = strength;
+        self
+    }
+
+    pub fn pull(mut self, pull: Pull) -> Config {
+        self.pull = pull;
+        self
+    }
+
+    pub fn hysteresis(mut self) -> Config {
+        self.hysteresis = true;
+        self
+    }
+
+    pub fn wakeup(mut self, wakeup: Wakeup) -> Config {
+        self.wakeup = wakeup;
+        self
+    }
+
+    /// Detect `edge`, raising the GPIO interrupt on it if `interrupt` is set.
+    pub fn edge(mut self, edge: Edge, interrupt: bool) -> Config {
+        self.edge = edge;
+        self.edge_interrupt = interrupt;
+        self
+    }
+
+    /// Enable the input buffer, needed to read the pin or route it to a peripheral input.
+    pub fn input(mut self) -> Config {
+        self.input = true;
+        self
+    }
+
+    fn value(&self) -> FieldValue<u32, IoConfiguration::Register> {
+        let io_mode = match self.io_mode {
+            IoMode::Normal => IoConfiguration::IO_MODE::Normal,
+            IoMode::Inverted => IoConfiguration::IO_MODE::Inverted,
+            IoMode::OpenDrain => IoConfiguration::IO_MODE::OpenDrain,
+            IoMode::OpenDrainInverted => IoConfiguration::IO_MODE::OpenDrainInverted,
+            IoMode::OpenSource => IoConfiguration::IO_MODE::OpenSource,
+            IoMode::OpenSourceInverted => IoConfiguration::IO_MODE::OpenSourceInverted,
+        };
+        let current = match self.current {
+            CurrentMode::Current2mA => IoConfiguration::IOCURR::Current2mA,
+            CurrentMode::Current4mA => IoConfiguration::IOCURR::Current4mA,
+            CurrentMode::Current8mA => IoConfiguration::IOCURR::Current8mA,
+        };
+        let strength = match self.strength {
+            DriveStrength::Auto => IoConfiguration::IOSTR::Auto,
+            DriveStrength::Min => IoConfiguration::IOSTR::Min,
+            DriveStrength::Med => IoConfiguration::IOSTR::Med,
+            DriveStrength::Max => IoConfiguration::IOSTR::Max,
+        };
+
+        IoConfiguration::PORT_ID.val(self.port.id()) + io_mode
+            + IoConfiguration::SLEW_RED.val(self.slew_reduction as u32) + current + strength
+            + pull_field(self.pull) + IoConfiguration::HYST_EN.val(self.hysteresis as u32)
+            + wakeup_field(self.wakeup) + edge_field(self.edge)
+            + IoConfiguration::EDGE_IRQ_EN.val(self.edge_interrupt as u32)
+            + IoConfiguration::IE.val(self.input as u32)
+    }
+}
+
+fn pull_field(pull: Pull) -> FieldValue<u32, IoConfiguration::Register> {
+    match pull {
+        Pull::None => IoConfiguration::PULL_CTL::PullNone,
+        Pull::Up => IoConfiguration::PULL_CTL::PullUp,
+        Pull::Down => IoConfiguration::PULL_CTL::PullDown,
+    }
+}
+
+fn wakeup_field(wakeup: Wakeup) -> FieldValue<u32, IoConfiguration::Register> {
+    match wakeup {
+        Wakeup::None => IoConfiguration::WU_CFG::None,
+        Wakeup::OnLow => IoConfiguration::WU_CFG::OnLow,
+        Wakeup::OnHigh => IoConfiguration::WU_CFG::OnHigh,
+    }
+}
+
+fn edge_field(edge: Edge) -> FieldValue<u32, IoConfiguration::Register> {
+    match edge {
+        Edge::None => IoConfiguration::EDGE_DET::None,
+        Edge::Falling => IoConfiguration::EDGE_DET::NegativeEdge,
+        Edge::Rising => IoConfiguration::EDGE_DET::PositiveEdge,
+        Edge::Both => IoConfiguration::EDGE_DET::EitherEdge,
+    }
 }
 
 #[repr(C)]
@@ -46,8 +287,21 @@ pub struct IocRegisters {
 register_bitfields![
     u32,
     IoConfiguration [
+        HYST_EN     OFFSET(30) NUMBITS(1) [], // Input hysteresis
         IE          OFFSET(29) NUMBITS(1) [], // Input Enable
-        IO_MODE     OFFSET(24) NUMBITS(3) [],
+        WU_CFG      OFFSET(27) NUMBITS(2) [
+            None    = 0b00,
+            OnLow   = 0b10,
+            OnHigh  = 0b11
+        ],
+        IO_MODE     OFFSET(24) NUMBITS(3) [
+            Normal              = 0b000,
+            Inverted            = 0b001,
+            OpenDrain           = 0b100,
+            OpenDrainInverted   = 0b101,
+            OpenSource          = 0b110,
+            OpenSourceInverted  = 0b111
+        ],
         EDGE_IRQ_EN OFFSET(18) NUMBITS(1) [], // Interrupt enable
         EDGE_DET    OFFSET(16) NUMBITS(2) [
             None            = 0b00,
@@ -60,10 +314,19 @@ register_bitfields![
             PullUp   = 0b10,
             PullNone = 0b11
         ],
-        PORT_ID     OFFSET(0) NUMBITS(6) [
-            GPIO = 0x00
-            // Add more as needed from datasheet p.1028
-        ]
+        SLEW_RED    OFFSET(12) NUMBITS(1) [], // Reduced output slew rate
+        IOCURR      OFFSET(10) NUMBITS(2) [
+            Current2mA = 0b00,
+            Current4mA = 0b01,
+            Current8mA = 0b10
+        ],
+        IOSTR       OFFSET(8) NUMBITS(2) [
+            Auto    = 0b00,
+            Min     = 0b01,
+            Med     = 0b10,
+            Max     = 0b11
+        ],
+        PORT_ID     OFFSET(0) NUMBITS(6) []
     ]
 ];
 
@@ -78,123 +341,62 @@ impl IocfgPin {
         IocfgPin { pin: pin as usize }
     }
 
-    pub fn enable_gpio(&self) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        // In order to configure the pin for GPIO we need to clear
-        // the lower 6 bits.
-        pin_ioc.write(IoConfiguration::PORT_ID::GPIO);
-    }
-
-    pub fn enable_uart_rx(&self) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        pin_ioc.set(pin_ioc.get() | IOC_UART0_RX_ID);
-        self.set_input_mode(hil::gpio::InputMode::PullNone);
-        self.enable_input();
-    }
-
-    pub fn enable_uart_tx(&self) {
+    fn register(&self) -> &ReadWrite<u32, IoConfiguration::Register> {
         let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        pin_ioc.set(pin_ioc.get() | IOC_UART0_TX_ID);
-        self.set_input_mode(hil::gpio::InputMode::PullNone);
-        self.enable_output();
+        &regs.iocfg[self.pin]
     }
 
-    pub fn enable_i2c_sda(&self) {
-        self.set_input_mode(hil::gpio::InputMode::PullNone);
-
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        pin_ioc.set(IOC_I2C_MSSDA | IOC_IOMODE_OPEN_DRAIN_NORMAL | IOC_IOPULL_UP); // This will reset previous config
-        self.enable_input();
+    /// Replace the whole configuration of the pin.
+    pub fn configure(&self, config: Config) {
+        self.register().write(config.value());
     }
 
-    pub fn enable_i2c_scl(&self) {
-        self.set_input_mode(hil::gpio::InputMode::PullNone);
-
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        pin_ioc.set(IOC_I2C_MSSCL | IOC_IOMODE_OPEN_DRAIN_NORMAL | IOC_IOPULL_UP); // This will reset previous config
-        self.enable_input();
+    /// Route the pin to the GPIO module, keeping the rest of its configuration.
+    pub fn enable_gpio(&self) {
+        self.register()
+            .modify(IoConfiguration::PORT_ID.val(PortId::Gpio.id()));
     }
 
     pub fn set_input_mode(&self, mode: hil::gpio::InputMode) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        let field = match mode {
-            hil::gpio::InputMode::PullDown => IoConfiguration::PULL_CTL::PullDown,
-            hil::gpio::InputMode::PullUp => IoConfiguration::PULL_CTL::PullUp,
-            hil::gpio::InputMode::PullNone => IoConfiguration::PULL_CTL::PullNone,
-        };
-
-        pin_ioc.modify(field);
-    }
-
-    pub fn set_hyst(&self, enable: bool) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        if enable {
-            pin_ioc.set(pin_ioc.get() | IOC_HYST_ENABLE);
-        } else {
-            pin_ioc.set(pin_ioc.get() & !IOC_HYST_ENABLE);
-        }
-    }
-
-    pub fn set_drv_strength(&self, current: CurrentMode, strength: DriveStrength) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        pin_ioc.set(pin_ioc.get() & !IOC_DRIVE_STRENGTH & !IOC_CURRENT_MODE);
-        pin_ioc.set(pin_ioc.get() | (current as u32) | (strength as u32));
+        self.register().modify(pull_field(match mode {
+            hil::gpio::InputMode::PullDown => Pull::Down,
+            hil::gpio::InputMode::PullUp => Pull::Up,
+            hil::gpio::InputMode::PullNone => Pull::None,
+        }));
     }
 
     pub fn enable_output(&self) {
         // Enable by disabling input
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-        pin_ioc.modify(IoConfiguration::IE::CLEAR);
+        self.register().modify(IoConfiguration::IE::CLEAR);
     }
 
     pub fn enable_input(&self) {
         // Set IE (Input Enable) bit
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-        pin_ioc.modify(IoConfiguration::IE::SET);
+        self.register().modify(IoConfiguration::IE::SET);
     }
 
     pub fn enable_interrupt(&self, mode: hil::gpio::InterruptMode) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-
-        let ioc_edge_mode = match mode {
-            hil::gpio::InterruptMode::FallingEdge => IoConfiguration::EDGE_DET::NegativeEdge,
-            hil::gpio::InterruptMode::RisingEdge => IoConfiguration::EDGE_DET::PositiveEdge,
-            hil::gpio::InterruptMode::EitherEdge => IoConfiguration::EDGE_DET::EitherEdge,
+        let edge = match mode {
+            hil::gpio::InterruptMode::FallingEdge => Edge::Falling,
+            hil::gpio::InterruptMode::RisingEdge => Edge::Rising,
+            hil::gpio::InterruptMode::EitherEdge => Edge::Both,
         };
 
-        pin_ioc.modify(ioc_edge_mode + IoConfiguration::EDGE_IRQ_EN::SET);
+        self.register()
+            .modify(edge_field(edge) + IoConfiguration::EDGE_IRQ_EN::SET);
     }
 
     pub fn disable_interrupt(&self) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-        pin_ioc.modify(IoConfiguration::EDGE_IRQ_EN::CLEAR);
+        self.register().modify(IoConfiguration::EDGE_IRQ_EN::CLEAR);
     }
 
+    pub fn set_wakeup(&self, wakeup: Wakeup) {
+        self.register().modify(wakeup_field(wakeup));
+    }
+
+    /// Disconnect the pin: GPIO with the input buffer off and no pull.
     pub fn low_leakage_mode(&self) {
-        let regs: &IocRegisters = unsafe { &*IOC_BASE };
-        let pin_ioc = &regs.iocfg[self.pin];
-        pin_ioc.set(0);
-        self.set_input_mode(hil::gpio::InputMode::PullNone);
+        self.configure(Config::new(PortId::Gpio));
     }
 }
 
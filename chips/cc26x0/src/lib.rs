@@ -4,6 +4,7 @@
 #![crate_type = "rlib"]
 extern crate cc26xx;
 extern crate cortexm3;
+extern crate rtc_ticks;
 #[allow(unused_imports)]
 #[macro_use]
 extern crate kernel;
@@ -12,9 +13,12 @@ extern crate kernel;
 extern crate bitfield;
 
 pub mod aon;
+pub mod ccfg;
 pub mod chip;
 pub mod crt1;
+pub mod delay;
 pub mod uart;
+pub mod ssi;
 pub mod i2c;
 pub mod sensor;
 pub mod hdc;
@@ -23,10 +27,15 @@ pub mod radio;
 pub mod timer;
 pub mod osc;
 pub mod ioc;
+pub mod pinmap;
+pub mod pins;
 pub mod prcm;
 pub mod rtc;
 pub mod gpio;
 pub mod tmp;
+pub mod watchdog;
+pub mod ext_flash;
+pub mod flash;
 
 pub mod power;
 pub mod peripherals;
@@ -66,7 +66,7 @@
 //! match sleep_mode {
 //!     SleepMode::DeepSleep => unsafe {
 //!         peripherals::M.before_sleep(sleep_mode as u32);
-//!         power::prepare_deep_sleep();
+//!         power::prepare_deep_sleep(self.board);
 //!     },
 //!     _ => (),
 //! }
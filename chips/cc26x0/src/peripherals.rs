@@ -1,6 +1,10 @@
 use uart;
 use tmp;
 use radio;
+use ssi;
+use ext_flash;
+use timer;
+use watchdog;
 use peripheral_manager::{Peripheral, PeripheralManager};
 
 pub static mut M: PeripheralManager = PeripheralManager::new();
@@ -9,10 +13,37 @@ static mut UART_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&uart
 
 static mut TMP007_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&tmp::TMP007_SENSOR) };
 
+static mut SSI_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&ssi::SSI0) };
+
+static mut EXT_FLASH_PERIPHERAL: Peripheral<'static> =
+    unsafe { Peripheral::new(&ext_flash::FLASH) };
+
+static mut GPT0_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&timer::GPT0) };
+static mut GPT1_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&timer::GPT1) };
+static mut GPT2_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&timer::GPT2) };
+static mut GPT3_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&timer::GPT3) };
+
+static mut WATCHDOG_PERIPHERAL: Peripheral<'static> =
+    unsafe { Peripheral::new(&watchdog::WATCHDOG) };
+
 static mut BLE_PERIPHERAL: Peripheral<'static> = unsafe { Peripheral::new(&radio::BLE) };
 
 pub unsafe fn init() {
-    let peripherals = [&UART_PERIPHERAL, &TMP007_PERIPHERAL, &BLE_PERIPHERAL];
+    // Peripherals are notified in the reverse order of registration, the external
+    // flash needs the SSI to be running when it is put into deep power-down.
+    // The watchdog is petted last before deep sleep.
+    let peripherals = [
+        &WATCHDOG_PERIPHERAL,
+        &UART_PERIPHERAL,
+        &TMP007_PERIPHERAL,
+        &BLE_PERIPHERAL,
+        &GPT0_PERIPHERAL,
+        &GPT1_PERIPHERAL,
+        &GPT2_PERIPHERAL,
+        &GPT3_PERIPHERAL,
+        &SSI_PERIPHERAL,
+        &EXT_FLASH_PERIPHERAL,
+    ];
 
     for peripheral in peripherals.iter() {
         M.register_peripheral(peripheral);
@@ -0,0 +1,51 @@
+//! Board pin maps
+//!
+//! Every signal of a board with the DIO it is connected to. Each board defines its
+//! table and hands it to the chip crate, which sets up the board specific defaults
+//! from it: the low power state of the pins (`gpio::power_on_gpio` and the chip,
+//! before deep sleep) and the pins of the I2C buses (`I2C::claim_pins`). The board
+//! wires its own drivers from the same table, so supporting another cc26x0 board,
+//! like the CC2650 LaunchPad, means writing its table.
+
+/// The DIOs of the signals of a board.
+pub struct PinMap {
+    pub led_red: usize,
+    pub led_green: usize,
+    /// Buttons are active low.
+    pub button_1: usize,
+    pub button_2: usize,
+    /// High while a magnet is close.
+    pub reed_relay: usize,
+    pub buzzer: usize,
+
+    pub uart_tx: usize,
+    pub uart_rx: usize,
+
+    /// Sensor bus, shared by the temperature, humidity, pressure and light sensors.
+    pub i2c_sda: usize,
+    pub i2c_scl: usize,
+    /// Second sensor bus, dedicated to the motion sensor.
+    pub i2c_sda_hp: usize,
+    pub i2c_scl_hp: usize,
+    pub tmp_rdy: usize,
+    pub mpu_int: usize,
+    pub mpu_power: usize,
+
+    /// SPI bus of the external flash and the DevPack.
+    pub spi_mosi: usize,
+    pub spi_miso: usize,
+    pub spi_clk: usize,
+    pub flash_cs: usize,
+
+    pub mic_power: usize,
+    pub audio_di: usize,
+    pub audio_clk: usize,
+
+    /// DevPack connector, for plug-in boards like the LCD or the debugger.
+    pub devpack_cs: usize,
+    pub dp0: usize,
+    pub dp1: usize,
+    pub dp2: usize,
+    pub dp3: usize,
+    pub devpack_id: usize,
+}
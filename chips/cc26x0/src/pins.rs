@@ -0,0 +1,34 @@
+//! Pin ownership
+//!
+//! Each DIO can only serve one driver. Drivers claim the pins they use while the
+//! board is set up, and claiming a pin that already belongs to another driver
+//! panics with both owners in the message. A conflict, like handing a sensor bus
+//! pin to userspace GPIO, therefore stops the first boot instead of showing up
+//! later as a peripheral that misbehaves.
+//!
+//! Claiming a pin again for the same owner is allowed, since drivers can be set up
+//! more than once (e.g. the UART by the serial loader and then by the console).
+//! Claims are never released.
+
+pub const NUM_PINS: usize = 32;
+
+static mut OWNERS: [Option<&'static str>; NUM_PINS] = [None; NUM_PINS];
+
+/// Claim `pin` for `owner`, panicking if another owner already holds it.
+pub fn claim(pin: usize, owner: &'static str) {
+    let owners = unsafe { &mut OWNERS };
+    match owners[pin] {
+        Some(current) if current != owner => {
+            panic!("DIO {} claimed by {} is already used by {}", pin, owner, current)
+        }
+        _ => owners[pin] = Some(owner),
+    }
+}
+
+pub fn owner(pin: usize) -> Option<&'static str> {
+    unsafe { OWNERS[pin] }
+}
+
+pub fn is_claimed(pin: usize) -> bool {
+    owner(pin).is_some()
+}
@@ -10,6 +10,7 @@ use setup::recharge;
 use rtc;
 use osc;
 use gpio;
+use pinmap::PinMap;
 
 pub static mut PM: PowerManager<RegionManager> = PowerManager::new(RegionManager);
 
@@ -57,8 +58,8 @@ pub fn switch_to_rc_oscillator() {
 }
 
 /// Transition into deep sleep
-pub unsafe fn prepare_deep_sleep() {
-    gpio::set_pins_to_default_conf();
+pub unsafe fn prepare_deep_sleep(board: &PinMap) {
+    gpio::set_pins_to_default_conf(board);
 
     switch_to_rc_oscillator();
 
@@ -53,9 +53,9 @@ struct PrcmRegisters {
     pub gpio_clk_gate_sleep: ReadWrite<u32, ClockGate::Register>,
     pub gpio_clk_gate_deep_sleep: ReadWrite<u32, ClockGate::Register>,
 
-    pub gpt_clk_gate_run: ReadWrite<u32, ClockGate::Register>,
-    pub gpt_clk_gate_sleep: ReadWrite<u32, ClockGate::Register>,
-    pub gpt_clk_gate_deep_sleep: ReadWrite<u32, ClockGate::Register>,
+    pub gpt_clk_gate_run: ReadWrite<u32, GPTClockGate::Register>,
+    pub gpt_clk_gate_sleep: ReadWrite<u32, GPTClockGate::Register>,
+    pub gpt_clk_gate_deep_sleep: ReadWrite<u32, GPTClockGate::Register>,
 
     pub i2c_clk_gate_run: ReadWrite<u32, ClockGate::Register>,
     pub i2c_clk_gate_sleep: ReadWrite<u32, ClockGate::Register>,
@@ -65,7 +65,11 @@ struct PrcmRegisters {
     pub uart_clk_gate_sleep: ReadWrite<u32, ClockGate::Register>,
     pub uart_clk_gate_deep_sleep: ReadWrite<u32, ClockGate::Register>,
 
-    _reserved3: [ReadOnly<u8>; 0xB4],
+    pub ssi_clk_gate_run: ReadWrite<u32, SSIClockGate::Register>,
+    pub ssi_clk_gate_sleep: ReadWrite<u32, SSIClockGate::Register>,
+    pub ssi_clk_gate_deep_sleep: ReadWrite<u32, SSIClockGate::Register>,
+
+    _reserved3: [ReadOnly<u8>; 0xA8],
 
     // Power domain control 0
     pub pd_ctl0: ReadWrite<u32, PowerDomain0::Register>,
@@ -120,6 +124,14 @@ register_bitfields![
     ClockGate [
         CLK_EN  OFFSET(0) NUMBITS(1) []
     ],
+    GPTClockGate [
+        // One bit per timer, GPT0 in bit 0 through GPT3 in bit 3
+        CLK_EN  OFFSET(0) NUMBITS(4) []
+    ],
+    SSIClockGate [
+        SSI1_CLK_EN OFFSET(1) NUMBITS(1) [],
+        SSI0_CLK_EN OFFSET(0) NUMBITS(1) []
+    ],
     PowerDomain0 [
         PERIPH_ON   OFFSET(2) NUMBITS(1) [],
         SERIAL_ON   OFFSET(1) NUMBITS(1) [],
@@ -308,6 +320,22 @@ impl Clock {
         prcm_commit();
     }
 
+    pub fn enable_ssi() {
+        let regs: &PrcmRegisters = unsafe { &*PRCM_BASE };
+        regs.ssi_clk_gate_run.modify(SSIClockGate::SSI0_CLK_EN::SET);
+        regs.ssi_clk_gate_sleep.modify(SSIClockGate::SSI0_CLK_EN::SET);
+        regs.ssi_clk_gate_deep_sleep.modify(SSIClockGate::SSI0_CLK_EN::SET);
+        prcm_commit();
+    }
+
+    pub fn disable_ssi() {
+        let regs: &PrcmRegisters = unsafe { &*PRCM_BASE };
+        regs.ssi_clk_gate_run.modify(SSIClockGate::SSI0_CLK_EN::CLEAR);
+        regs.ssi_clk_gate_sleep.modify(SSIClockGate::SSI0_CLK_EN::CLEAR);
+        regs.ssi_clk_gate_deep_sleep.modify(SSIClockGate::SSI0_CLK_EN::CLEAR);
+        prcm_commit();
+    }
+
     pub fn enable_trng() {
         let regs: &PrcmRegisters = unsafe { &*PRCM_BASE };
         regs.sec_dma_clk_run
@@ -340,11 +368,21 @@ impl Clock {
         prcm_commit();
     }
 
-    pub fn enable_gpt() {
+    pub fn enable_gpt(timer: u32) {
+        let regs: &PrcmRegisters = unsafe { &*PRCM_BASE };
+        let enabled = regs.gpt_clk_gate_run.read(GPTClockGate::CLK_EN) | (1 << timer);
+        regs.gpt_clk_gate_run.write(GPTClockGate::CLK_EN.val(enabled));
+        regs.gpt_clk_gate_sleep.write(GPTClockGate::CLK_EN.val(enabled));
+        regs.gpt_clk_gate_deep_sleep.write(GPTClockGate::CLK_EN.val(enabled));
+        prcm_commit();
+    }
+
+    pub fn disable_gpt(timer: u32) {
         let regs: &PrcmRegisters = unsafe { &*PRCM_BASE };
-        regs.gpt_clk_gate_run.write(ClockGate::CLK_EN::SET);
-        regs.gpt_clk_gate_sleep.write(ClockGate::CLK_EN::SET);
-        regs.gpt_clk_gate_deep_sleep.write(ClockGate::CLK_EN::SET);
+        let enabled = regs.gpt_clk_gate_run.read(GPTClockGate::CLK_EN) & !(1 << timer);
+        regs.gpt_clk_gate_run.write(GPTClockGate::CLK_EN.val(enabled));
+        regs.gpt_clk_gate_sleep.write(GPTClockGate::CLK_EN.val(enabled));
+        regs.gpt_clk_gate_deep_sleep.write(GPTClockGate::CLK_EN.val(enabled));
         prcm_commit();
     }
 
@@ -4,8 +4,8 @@
 
 use core::cell::Cell;
 use self::ble_commands::*;
+use delay;
 use osc;
-use rtc;
 use radio::rfc::{self, rfc_commands};
 
 use kernel;
@@ -24,6 +24,8 @@ static mut BLE_OVERRIDES: [u32; 7] = [
     0x00456088 /* Adjust AGC reference level */, 0xFFFFFFFF /* End of override list */,
 ];
 
+const COMMAND_SETTLE_TIME_US: u32 = 1_000;
+
 /*
     We need to use static buffers in order to make them
     constantly accessible by the radio MCU (we need to assure that they
@@ -195,9 +197,8 @@ impl Ble {
 
 impl rfc::RFCoreClient for Ble {
     fn command_done(&self) {
-        for _i in 0..0xFFF {
-            unsafe { rtc::RTC.sync() };
-        }
+        // Give the radio time to settle before it might be powered down
+        delay::busy_wait_us(COMMAND_SETTLE_TIME_US);
 
         if self.schedule_powerdown.get() {
             self.power_down();
@@ -17,6 +17,7 @@
 
 // RFC Commands are located at the bottom
 use self::rfc_commands::*;
+use delay;
 use prcm;
 use rtc;
 
@@ -85,6 +86,10 @@ register_bitfields![
 const RFC_DBELL_BASE: *mut RfcBellRegisters = 0x4004_1000 as *mut RfcBellRegisters;
 const RFC_PWR_BASE: *mut VolatileCell<u32> = 0x4004_0000 as *mut VolatileCell<u32>;
 
+// Longest wait for the radio MCU to acknowledge a command, and to finish one
+const CMD_ACK_TIMEOUT_US: u32 = 10_000;
+const CMD_DONE_TIMEOUT_US: u32 = 100_000;
+
 /*
     RFC Immediate commands
 */
@@ -427,19 +432,17 @@ impl RFCore {
 
 
         // Wait for ACK from the radio MCU
-        let mut timeout: u32 = 0;
         let mut status = 0;
-        const MAX_TIMEOUT: u32 = 0x2FFFFFF;
-        while timeout < MAX_TIMEOUT {
+        let acked = delay::wait_for(CMD_ACK_TIMEOUT_US, || {
             status = bell_regs.cmdsta.get();
-            if (status & 0xFF) == 0x01 {
-                return Ok(());
-            }
+            (status & 0xFF) == 0x01
+        });
 
-            timeout += 1;
+        if acked {
+            Ok(())
+        } else {
+            Err(status)
         }
-
-        Err(status)
     }
 
     /*
@@ -449,19 +452,17 @@ impl RFCore {
     fn wait_cmdr(&self, command: u32) -> RfcResult {
         let command_regs: &RfcCommandCommon = unsafe { &*(command as *const RfcCommandCommon) };
 
-        let mut timeout: u32 = 0;
         let mut status = 0;
-        const MAX_TIMEOUT: u32 = 0x2FFFFFF;
-        while timeout < MAX_TIMEOUT {
+        let done = delay::wait_for(CMD_DONE_TIMEOUT_US, || {
             status = command_regs.status.get();
-            if status == 0x0400 {
-                return Ok(());
-            }
+            status == 0x0400
+        });
 
-            timeout += 1;
+        if done {
+            Ok(())
+        } else {
+            Err(status as u32)
         }
-
-        Err(status as u32)
     }
 
     pub fn handle_interrupt(&self, int: RfcInterrupt) {
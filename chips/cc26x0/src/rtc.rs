@@ -1,9 +1,50 @@
 //! RTC driver, sensortag family
-
+//!
+//! The AON RTC is a 64-bit counter of seconds (`sec`) and 1/2^32 parts of a second
+//! (`subsec`), incremented from the 32.768kHz LF clock and running through all
+//! sleep modes. The compare channels see bits 47:16 of it: the low 16 bits of the
+//! seconds and the high 16 bits of the fraction. The alarm counter (`Alarm::now`)
+//! is that same 32-bit window, so it counts at exactly 2^16 Hz and wraps every
+//! 2^16 seconds (~18.2 hours), exactly like the comparator does.
+//!
+//! Alarms are 32-bit tick values and are compared modulo 2^32, so an alarm set
+//! across the wrap fires at the right time. An alarm that is already due (or is
+//! less than `MIN_TICKS` away) fires after `MIN_TICKS` instead, since the
+//! comparator only matches on future LF clock edges.
+//!
+//! Each of the three channels has its own use:
+//!
+//! * Channel 0 is shared by the main alarm, implemented by `Rtc` itself, a second
+//!   independent alarm (`ALARM0`) and the timeslices of the scheduler
+//!   (`RtcSysTick`). Each of them only keeps a deadline, and the channel is armed
+//!   for whichever is due first, so the second alarm and the timeslice don't take
+//!   a compare channel of their own. The channel is only rearmed when the first
+//!   deadline changes. Like the Cortex-M SysTick the timeslice only counts down
+//!   while it is enabled, i.e. while a process runs. Unlike it, it doesn't depend
+//!   on the HF clock, which is switched to the RC oscillator around deep sleep.
+//! * Channel 1 is the only channel that can capture: with capture enabled it
+//!   latches the alarm counter whenever the AON event selected as capture source
+//!   happens (for example an edge on a DIO), and the `CaptureClient` receives the
+//!   timestamp. The capture is done in hardware, so it is accurate to one LF clock
+//!   period even when the event wakes the MCU from deep sleep.
+//! * Channel 2 runs in continuous mode, where the hardware adds the period to the
+//!   compare value on every match, giving a drift free periodic tick for a
+//!   `TickClient`.
+//!
+//! All enabled channels are part of the RTC combined event, which raises the RTC
+//! interrupt and (selected in `aon::Aon::setup`) wakes the MCU from deep sleep.
+
+use aon;
 use core::cell::Cell;
+use kernel;
 use kernel::common::regs::{ReadOnly, ReadWrite};
 use kernel::hil::time::{self, Alarm, Time};
 use kernel::hil::time::Frequency;
+use rtc_ticks::{compare_value, ticks_until};
+
+// The tick arithmetic lives in its own crate, which builds and is tested on the host
+pub use rtc_ticks::{ms_to_ticks, ticks_from_time, ticks_to_us, us_to_ticks, MIN_TICKS,
+                    TICKS_PER_SECOND};
 
 #[repr(C)]
 pub struct RtcRegisters {
@@ -19,11 +60,13 @@ pub struct RtcRegisters {
 
     _subsec_inc: ReadOnly<u32>,
     channel_ctl: ReadWrite<u32, ChannelControl::Register>,
-    _channel0_cmp: ReadOnly<u32>,
+    channel0_cmp: ReadWrite<u32>,
     channel1_cmp: ReadWrite<u32>,
-    _channel2_cmp: ReadOnly<u32>,
-    _channel2_cmp_inc: ReadOnly<u32>,
-    _channel1_capture: ReadOnly<u32>,
+    channel2_cmp: ReadWrite<u32>,
+    // Added to the channel 2 compare value on every match in continuous mode
+    channel2_cmp_inc: ReadWrite<u32>,
+    // Alarm counter value latched by the last capture event
+    channel1_capture: ReadOnly<u32>,
 
     // A read request to the sync register will not return
     // until all outstanding writes have properly propagated to the RTC domain
@@ -33,11 +76,12 @@ pub struct RtcRegisters {
 register_bitfields![
     u32,
     Control [
+        // Channels included in the combined event, one bit per channel
         COMB_EV_MASK OFFSET(16) NUMBITS(3) [
-            NoEvent = 0b00,
-            Channel0 = 0b01,
-            Channel1 = 0b10,
-            Channel2 = 0b11
+            NoEvent = 0b000,
+            Channel0 = 0b001,
+            Channel1 = 0b010,
+            Channel2 = 0b100
         ],
         RESET       OFFSET(7) NUMBITS(1) [],
         RTC_UPD_EN  OFFSET(1) NUMBITS(1) [],
@@ -59,9 +103,53 @@ register_bitfields![
 
 const RTC_BASE: *const RtcRegisters = 0x4009_2000 as *const RtcRegisters;
 
+#[derive(Copy, Clone, PartialEq)]
+enum Channel {
+    Channel0,
+    Channel1,
+    Channel2,
+}
+
+impl Channel {
+    fn mask(self) -> u32 {
+        match self {
+            Channel::Channel0 => 0b001,
+            Channel::Channel1 => 0b010,
+            Channel::Channel2 => 0b100,
+        }
+    }
+}
+
+/// The deadlines sharing compare channel 0, an index into `Rtc::deadlines`.
+#[derive(Copy, Clone, PartialEq)]
+enum Deadline {
+    Alarm = 0,
+    Alarm0 = 1,
+    Timeslice = 2,
+}
+
+const NUM_DEADLINES: usize = 3;
+
+pub trait TickClient {
+    fn tick(&self);
+}
+
+pub trait CaptureClient {
+    /// The capture source happened at alarm counter value `tics`.
+    fn captured(&self, tics: u32);
+}
+
 pub struct Rtc {
     regs: *const RtcRegisters,
     callback: Cell<Option<&'static time::Client>>,
+    tick_client: Cell<Option<&'static TickClient>>,
+    capture_client: Cell<Option<&'static CaptureClient>>,
+    // Deadlines sharing channel 0
+    deadlines: [Cell<Option<u32>>; NUM_DEADLINES],
+    // Which of the deadlines the armed channel 0 compare value stands for
+    armed: [Cell<bool>; NUM_DEADLINES],
+    // The deadline channel 0 is armed for, None while it is disabled
+    target: Cell<Option<u32>>,
 }
 
 pub static mut RTC: Rtc = Rtc::new();
@@ -71,13 +159,17 @@ impl Rtc {
         Rtc {
             regs: RTC_BASE,
             callback: Cell::new(None),
+            tick_client: Cell::new(None),
+            capture_client: Cell::new(None),
+            deadlines: [Cell::new(None), Cell::new(None), Cell::new(None)],
+            armed: [Cell::new(false), Cell::new(false), Cell::new(false)],
+            target: Cell::new(None),
         }
     }
 
     pub fn start(&self) {
         let regs: &RtcRegisters = unsafe { &*self.regs };
         regs.ctl.write(Control::ENABLE::SET);
-        regs.ctl.modify(Control::COMB_EV_MASK.val(0b10));
 
         regs.sync.get();
     }
@@ -94,7 +186,8 @@ impl Rtc {
         regs.sync.get();
     }
 
-    pub fn read_counter(&self) -> u32 {
+    /// Read the seconds and the fractional (1/2^32) part of the current second.
+    pub fn read_time(&self) -> (u32, u32) {
         let regs: &RtcRegisters = unsafe { &*self.regs };
 
         /*
@@ -110,29 +203,257 @@ impl Rtc {
             after_subsec_read = regs.sec.get();
         }
 
-        return (current_sec << 16) | (current_subsec >> 16);
+        (current_sec, current_subsec)
     }
 
-    pub fn is_running(&self) -> bool {
+    pub fn read_counter(&self) -> u32 {
+        let (sec, subsec) = self.read_time();
+        ticks_from_time(sec, subsec)
+    }
+
+    pub fn is_enabled(&self) -> bool {
         let regs: &RtcRegisters = unsafe { &*self.regs };
-        regs.channel_ctl.read(ChannelControl::CH1_EN) != 0
+        regs.ctl.is_set(Control::ENABLE)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.deadline(Deadline::Alarm).is_some()
     }
 
     pub fn handle_interrupt(&self) {
         let regs: &RtcRegisters = unsafe { &*self.regs };
+        let ch0 = regs.evflags.is_set(EvFlags::CH0)
+            && regs.channel_ctl.is_set(ChannelControl::CH0_EN);
+        let ch1 = regs.evflags.is_set(EvFlags::CH1)
+            && regs.channel_ctl.is_set(ChannelControl::CH1_EN);
+        let ch2 = regs.evflags.is_set(EvFlags::CH2)
+            && regs.channel_ctl.is_set(ChannelControl::CH2_EN);
+
+        // Channel 0 is one-shot, the capture and the tick keep running. An
+        // expired timeslice needs no handling: the interrupt itself switched back
+        // to the kernel, which then sees `RtcSysTick::overflowed`.
+        let mut fired = [false; NUM_DEADLINES];
+        if ch0 {
+            self.disable_channel(Channel::Channel0);
+            self.target.set(None);
+            for (i, fired) in fired.iter_mut().enumerate() {
+                if self.armed[i].get() {
+                    self.deadlines[i].set(None);
+                    *fired = true;
+                }
+            }
+            self.schedule_channel0();
+        }
+        let captured = if ch1 {
+            regs.evflags.write(EvFlags::CH1::SET);
+            Some(regs.channel1_capture.get())
+        } else {
+            None
+        };
+        if ch2 {
+            // Event flag is cleared when you set it
+            regs.evflags.write(EvFlags::CH2::SET);
+        }
+        regs.sync.get();
+
+        if fired[Deadline::Alarm as usize] {
+            self.callback.get().map(|cb| cb.fired());
+        }
+        if fired[Deadline::Alarm0 as usize] {
+            unsafe {
+                ALARM0.client.get().map(|cb| cb.fired());
+            }
+        }
+        if let Some(tics) = captured {
+            self.capture_client.get().map(|client| client.captured(tics));
+        }
+        if ch2 {
+            self.tick_client.get().map(|client| client.tick());
+        }
+    }
+
+    pub fn set_client(&self, client: &'static time::Client) {
+        self.callback.set(Some(client));
+    }
+
+    pub fn set_tick_client(&self, client: &'static TickClient) {
+        self.tick_client.set(Some(client));
+    }
+
+    pub fn set_capture_client(&self, client: &'static CaptureClient) {
+        self.capture_client.set(Some(client));
+    }
+
+    /// Call the tick client every `period` ticks, starting `period` ticks from now.
+    pub fn start_tick(&self, period: u32) {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+        let period = period.max(MIN_TICKS);
+
+        regs.channel_ctl
+            .modify(ChannelControl::CH2_EN::CLEAR + ChannelControl::CH2_CONT_EN::CLEAR);
+        regs.channel2_cmp_inc.set(period);
+        regs.channel2_cmp
+            .set(self.read_counter().wrapping_add(period));
+        regs.evflags.write(EvFlags::CH2::SET);
+        self.add_to_combined_event(Channel::Channel2);
+        regs.channel_ctl
+            .modify(ChannelControl::CH2_EN::SET + ChannelControl::CH2_CONT_EN::SET);
+
+        regs.sync.get();
+    }
+
+    pub fn stop_tick(&self) {
+        self.disable_channel(Channel::Channel2);
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+        regs.channel_ctl
+            .modify(ChannelControl::CH2_CONT_EN::CLEAR);
+
+        regs.sync.get();
+    }
+
+    pub fn is_ticking(&self) -> bool {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+        regs.channel_ctl.is_set(ChannelControl::CH2_EN)
+    }
+
+    /// Timestamp every occurrence of the AON `event` for the capture client.
+    ///
+    /// A DIO event is generated by the edge detection of the pin, which has to be
+    /// enabled in its IOC configuration (for example with the GPIO
+    /// `enable_interrupt`). Only one event can be captured at a time, and a new
+    /// event overwrites the previous timestamp if the interrupt wasn't serviced
+    /// in between.
+    pub fn start_capture(&self, event: aon::Event) {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
 
-        // Event flag is cleared when you set it
-        regs.evflags.write(EvFlags::CH1::SET);
-        regs.ctl.modify(Control::COMB_EV_MASK::NoEvent);
         regs.channel_ctl.modify(ChannelControl::CH1_EN::CLEAR);
+        aon::AON.set_rtc_capture_source(event);
+        regs.evflags.write(EvFlags::CH1::SET);
+        self.add_to_combined_event(Channel::Channel1);
+        regs.channel_ctl
+            .modify(ChannelControl::CH1_CAPT_EN::SET + ChannelControl::CH1_EN::SET);
 
         regs.sync.get();
+    }
 
-        self.callback.get().map(|cb| cb.fired());
+    pub fn stop_capture(&self) {
+        self.disable_channel(Channel::Channel1);
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+        regs.channel_ctl
+            .modify(ChannelControl::CH1_CAPT_EN::CLEAR);
+        aon::AON.set_rtc_capture_source(aon::Event::None);
+
+        regs.sync.get();
     }
 
-    pub fn set_client(&self, client: &'static time::Client) {
-        self.callback.set(Some(client));
+    pub fn is_capturing(&self) -> bool {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+        regs.channel_ctl.is_set(ChannelControl::CH1_EN)
+    }
+
+    fn deadline(&self, deadline: Deadline) -> Option<u32> {
+        self.deadlines[deadline as usize].get()
+    }
+
+    fn set_deadline(&self, deadline: Deadline, tics: Option<u32>) {
+        if self.deadline(deadline) == tics {
+            return;
+        }
+        self.deadlines[deadline as usize].set(tics);
+        self.schedule_channel0();
+    }
+
+    /// Arm channel 0 for the earliest of the deadlines.
+    fn schedule_channel0(&self) {
+        let now = self.read_counter();
+        let distance = |deadline: u32| ticks_until(now, deadline);
+
+        let next = match self.deadlines
+            .iter()
+            .filter_map(|deadline| deadline.get().map(&distance))
+            .min()
+        {
+            Some(next) => next,
+            None => {
+                for armed in self.armed.iter() {
+                    armed.set(false);
+                }
+                if self.target.get().is_some() {
+                    self.target.set(None);
+                    self.disable_channel(Channel::Channel0);
+                    self.sync();
+                }
+                return;
+            }
+        };
+
+        // Remember which deadlines the match stands for, so all of them fire if
+        // they are equal and none is mistaken for due after a late interrupt
+        for (deadline, armed) in self.deadlines.iter().zip(self.armed.iter()) {
+            armed.set(deadline.get().map(&distance) == Some(next));
+        }
+
+        // The AON writes and the sync are slow, skip them if the first deadline stays
+        let target = now.wrapping_add(next);
+        if self.target.get() != Some(target) {
+            self.target.set(Some(target));
+            self.arm_channel(Channel::Channel0, target);
+        }
+    }
+
+    /// Arm a compare channel as a one-shot alarm at `tics`.
+    fn arm_channel(&self, channel: Channel, tics: u32) {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+
+        let tics = compare_value(self.read_counter(), tics);
+
+        match channel {
+            Channel::Channel0 => {
+                regs.channel0_cmp.set(tics);
+                regs.evflags.write(EvFlags::CH0::SET);
+                regs.channel_ctl.modify(ChannelControl::CH0_EN::SET);
+            }
+            Channel::Channel1 => {
+                regs.channel1_cmp.set(tics);
+                regs.evflags.write(EvFlags::CH1::SET);
+                regs.channel_ctl.modify(ChannelControl::CH1_EN::SET);
+            }
+            Channel::Channel2 => {
+                regs.channel2_cmp.set(tics);
+                regs.evflags.write(EvFlags::CH2::SET);
+                regs.channel_ctl.modify(ChannelControl::CH2_EN::SET);
+            }
+        }
+        self.add_to_combined_event(channel);
+
+        regs.sync.get();
+    }
+
+    fn disable_channel(&self, channel: Channel) {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+
+        let mask = regs.ctl.read(Control::COMB_EV_MASK) & !channel.mask();
+        regs.ctl.modify(Control::COMB_EV_MASK.val(mask));
+        match channel {
+            Channel::Channel0 => {
+                regs.channel_ctl.modify(ChannelControl::CH0_EN::CLEAR);
+                regs.evflags.write(EvFlags::CH0::SET);
+            }
+            Channel::Channel1 => {
+                regs.channel_ctl.modify(ChannelControl::CH1_EN::CLEAR);
+                regs.evflags.write(EvFlags::CH1::SET);
+            }
+            Channel::Channel2 => {
+                regs.channel_ctl.modify(ChannelControl::CH2_EN::CLEAR);
+                regs.evflags.write(EvFlags::CH2::SET);
+            }
+        }
+    }
+
+    fn add_to_combined_event(&self, channel: Channel) {
+        let regs: &RtcRegisters = unsafe { &*self.regs };
+        let mask = regs.ctl.read(Control::COMB_EV_MASK) | channel.mask();
+        regs.ctl.modify(Control::COMB_EV_MASK.val(mask));
     }
 
     pub fn set_upd_en(&self, value: bool) {
@@ -148,11 +469,8 @@ impl Rtc {
 pub struct RtcFreq(());
 
 impl Frequency for RtcFreq {
-    // The RTC Frequency is tuned, as there is exactly 0xFFFF (64kHz)
-    // subsec increments to reach a second, this yields the correct
-    // `tics` to set the comparator correctly.
     fn frequency() -> u32 {
-        0xFFFF
+        TICKS_PER_SECOND
     }
 }
 
@@ -160,12 +478,7 @@ impl Time for Rtc {
     type Frequency = RtcFreq;
 
     fn disable(&self) {
-        let regs: &RtcRegisters = unsafe { &*self.regs };
-
-        regs.ctl.modify(Control::COMB_EV_MASK::NoEvent);
-        regs.channel_ctl.modify(ChannelControl::CH1_EN::CLEAR);
-
-        regs.sync.get();
+        self.set_deadline(Deadline::Alarm, None);
     }
 
     fn is_armed(&self) -> bool {
@@ -179,17 +492,147 @@ impl Alarm for Rtc {
     }
 
     fn set_alarm(&self, tics: u32) {
-        let regs: &RtcRegisters = unsafe { &*self.regs };
+        self.set_deadline(Deadline::Alarm, Some(tics));
+    }
 
-        regs.ctl.modify(Control::COMB_EV_MASK::Channel1);
-        regs.channel1_cmp.set(tics);
-        regs.channel_ctl.modify(ChannelControl::CH1_EN::SET);
+    fn get_alarm(&self) -> u32 {
+        self.deadline(Deadline::Alarm).unwrap_or(0)
+    }
+}
 
-        regs.sync.get();
+/// A second alarm, independent of the main alarm and sharing compare channel 0
+/// with it.
+pub struct Alarm0 {
+    client: Cell<Option<&'static time::Client>>,
+}
+
+pub static mut ALARM0: Alarm0 = Alarm0::new();
+
+impl Alarm0 {
+    const fn new() -> Alarm0 {
+        Alarm0 {
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static time::Client) {
+        self.client.set(Some(client));
+    }
+}
+
+impl Time for Alarm0 {
+    type Frequency = RtcFreq;
+
+    fn disable(&self) {
+        unsafe { RTC.set_deadline(Deadline::Alarm0, None) };
+    }
+
+    fn is_armed(&self) -> bool {
+        unsafe { RTC.deadline(Deadline::Alarm0).is_some() }
+    }
+}
+
+impl Alarm for Alarm0 {
+    fn now(&self) -> u32 {
+        unsafe { RTC.read_counter() }
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        unsafe { RTC.set_deadline(Deadline::Alarm0, Some(tics)) };
     }
 
     fn get_alarm(&self) -> u32 {
-        let regs: &RtcRegisters = unsafe { &*self.regs };
-        regs.channel1_cmp.get()
+        unsafe { RTC.deadline(Deadline::Alarm0).unwrap_or(0) }
+    }
+}
+
+/// The scheduler's timeslice timer, sharing compare channel 0 with the alarms.
+pub struct RtcSysTick {
+    // End of the timeslice while counting
+    deadline: Cell<u32>,
+    // Ticks left of the timeslice while not counting
+    left: Cell<u32>,
+    running: Cell<bool>,
+    counting: Cell<bool>,
+}
+
+impl RtcSysTick {
+    pub const fn new() -> RtcSysTick {
+        RtcSysTick {
+            deadline: Cell::new(0),
+            left: Cell::new(0),
+            running: Cell::new(false),
+            counting: Cell::new(false),
+        }
+    }
+
+    fn remaining(&self) -> u32 {
+        if !self.counting.get() {
+            return self.left.get();
+        }
+
+        let now = unsafe { RTC.read_counter() };
+        let remaining = self.deadline.get().wrapping_sub(now);
+        // Past the deadline the difference wraps to a huge value
+        if remaining > (1 << 31) {
+            0
+        } else {
+            remaining
+        }
+    }
+
+    /// Count down `ticks` from now, with the interrupt at the end.
+    fn start_counting(&self, ticks: u32) {
+        let now = unsafe { RTC.read_counter() };
+        self.deadline.set(now.wrapping_add(ticks));
+        unsafe { RTC.set_deadline(Deadline::Timeslice, Some(self.deadline.get())) };
+    }
+}
+
+impl kernel::SysTick for RtcSysTick {
+    fn set_timer(&self, us: u32) {
+        self.running.set(true);
+        if self.counting.get() {
+            self.start_counting(us_to_ticks(us));
+        } else {
+            self.left.set(us_to_ticks(us));
+        }
+    }
+
+    fn greater_than(&self, us: u32) -> bool {
+        ticks_to_us(self.remaining()) > us as u64
+    }
+
+    fn overflowed(&self) -> bool {
+        self.running.get() && self.remaining() == 0
+    }
+
+    fn reset(&self) {
+        if self.running.get() && self.counting.get() {
+            unsafe { RTC.set_deadline(Deadline::Timeslice, None) };
+        }
+        self.running.set(false);
+        self.counting.set(false);
+        self.left.set(0);
+    }
+
+    fn enable(&self, with_interrupt: bool) {
+        if with_interrupt == self.counting.get() {
+            return;
+        }
+
+        // Paused while the kernel runs, so syscalls aren't charged to the process
+        if with_interrupt {
+            self.counting.set(true);
+            if self.running.get() {
+                self.start_counting(self.left.get());
+            }
+        } else {
+            self.left.set(self.remaining());
+            self.counting.set(false);
+            if self.running.get() {
+                unsafe { RTC.set_deadline(Deadline::Timeslice, None) };
+            }
+        }
     }
 }
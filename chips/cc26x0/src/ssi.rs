@@ -0,0 +1,464 @@
+//! Synchronous Serial Interface (SSI), cc26x0 family
+//!
+//! For details see the SSI chapter in the cc2650 technical reference manual.
+//!
+//! Implements the SPI master HIL on top of SSI0 in Motorola SPI frame format with
+//! 8-bit words. Transfers are interrupt driven: the TX FIFO is kept topped up and
+//! drained from the RX FIFO on the RX (half full) and RX timeout interrupts.
+//!
+//! The chip select line is a regular GPIO pin, since the SSI only supports a single
+//! frame signal which is released between every word.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::regs::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::gpio::Pin;
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMasterClient};
+use kernel::ReturnCode;
+
+use chip;
+use gpio;
+use ioc;
+use peripheral_manager;
+use pins;
+use power::PM;
+use prcm;
+
+pub const SSI0_BASE: usize = 0x4000_0000;
+pub const MCU_CLOCK: u32 = 48_000_000;
+
+// Depth of both the TX and RX FIFOs
+const FIFO_DEPTH: usize = 8;
+
+#[repr(C)]
+struct Registers {
+    cr0: ReadWrite<u32, Control0::Register>,
+    cr1: ReadWrite<u32, Control1::Register>,
+    dr: ReadWrite<u32>,
+    sr: ReadOnly<u32, Status::Register>,
+    cpsr: ReadWrite<u32, ClockPrescale::Register>,
+    imsc: ReadWrite<u32, Interrupts::Register>,
+    _ris: ReadOnly<u32, Interrupts::Register>,
+    _mis: ReadOnly<u32, Interrupts::Register>,
+    icr: WriteOnly<u32, Interrupts::Register>,
+    _dmacr: ReadWrite<u32>,
+}
+
+register_bitfields![
+    u32,
+    Control0 [
+        // Serial clock rate: bit rate = clock / (CPSDVSR * (1 + SCR))
+        SCR OFFSET(8) NUMBITS(8) [],
+        SPH OFFSET(7) NUMBITS(1) [],
+        SPO OFFSET(6) NUMBITS(1) [],
+        FRF OFFSET(4) NUMBITS(2) [
+            MotorolaSpi = 0x0,
+            TiSync = 0x1,
+            Microwire = 0x2
+        ],
+        DSS OFFSET(0) NUMBITS(4) [
+            Len8 = 0x7
+        ]
+    ],
+    Control1 [
+        MS  OFFSET(2) NUMBITS(1) [], // 0 = Master, 1 = Slave
+        SSE OFFSET(1) NUMBITS(1) [], // Synchronous serial port enable
+        LBM OFFSET(0) NUMBITS(1) []  // Loop back mode
+    ],
+    Status [
+        BSY OFFSET(4) NUMBITS(1) [],
+        RFF OFFSET(3) NUMBITS(1) [],
+        RNE OFFSET(2) NUMBITS(1) [],
+        TNF OFFSET(1) NUMBITS(1) [],
+        TFE OFFSET(0) NUMBITS(1) []
+    ],
+    ClockPrescale [
+        // Must be an even number between 2 and 254
+        CPSDVSR OFFSET(0) NUMBITS(8) []
+    ],
+    Interrupts [
+        TX  OFFSET(3) NUMBITS(1) [], // TX FIFO half empty or less
+        RX  OFFSET(2) NUMBITS(1) [], // RX FIFO half full or more
+        RT  OFFSET(1) NUMBITS(1) [], // RX timeout
+        ROR OFFSET(0) NUMBITS(1) []  // RX overrun
+    ]
+];
+
+pub struct SSI {
+    regs: *const Registers,
+    client: Cell<Option<&'static SpiMasterClient>>,
+    mosi_pin: Cell<Option<u8>>,
+    miso_pin: Cell<Option<u8>>,
+    clk_pin: Cell<Option<u8>>,
+    chip_select: Cell<Option<u8>>,
+    hold_low: Cell<bool>,
+    enabled: Cell<bool>,
+    rate: Cell<u32>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    transfer_len: Cell<usize>,
+    tx_index: Cell<usize>,
+    rx_index: Cell<usize>,
+    busy: Cell<bool>,
+}
+
+pub static mut SSI0: SSI = SSI::new();
+
+impl SSI {
+    pub const fn new() -> SSI {
+        SSI {
+            regs: SSI0_BASE as *const Registers,
+            client: Cell::new(None),
+            mosi_pin: Cell::new(None),
+            miso_pin: Cell::new(None),
+            clk_pin: Cell::new(None),
+            chip_select: Cell::new(None),
+            hold_low: Cell::new(false),
+            enabled: Cell::new(false),
+            rate: Cell::new(1_000_000),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            transfer_len: Cell::new(0),
+            tx_index: Cell::new(0),
+            rx_index: Cell::new(0),
+            busy: Cell::new(false),
+        }
+    }
+
+    pub fn set_pins(&self, mosi_pin: u8, miso_pin: u8, clk_pin: u8) {
+        pins::claim(mosi_pin as usize, "SSI0 MOSI");
+        pins::claim(miso_pin as usize, "SSI0 MISO");
+        pins::claim(clk_pin as usize, "SSI0 CLK");
+        self.mosi_pin.set(Some(mosi_pin));
+        self.miso_pin.set(Some(miso_pin));
+        self.clk_pin.set(Some(clk_pin));
+    }
+
+    pub fn configure(&self) {
+        let mosi_pin = self.mosi_pin.get().expect("MOSI pin not configured for SSI");
+        let miso_pin = self.miso_pin.get().expect("MISO pin not configured for SSI");
+        let clk_pin = self.clk_pin.get().expect("CLK pin not configured for SSI");
+
+        unsafe {
+            // Map SSI signals to IO pins
+            ioc::IOCFG[mosi_pin as usize].configure(ioc::Config::new(ioc::PortId::Ssi0Tx));
+            ioc::IOCFG[miso_pin as usize].configure(ioc::Config::new(ioc::PortId::Ssi0Rx).input());
+            ioc::IOCFG[clk_pin as usize].configure(ioc::Config::new(ioc::PortId::Ssi0Clk));
+
+            // Keep the slave deselected until a transfer starts
+            self.chip_select.get().map(|cs| {
+                gpio::PORT[cs as usize].make_output();
+                gpio::PORT[cs as usize].set();
+            });
+        }
+
+        self.disable_interrupts();
+        self.set_format();
+    }
+
+    /// Write the frame format and bit rate, which requires the SSI to be disabled.
+    fn set_format(&self) {
+        let regs = unsafe { &*self.regs };
+
+        regs.cr1.modify(Control1::SSE::CLEAR);
+
+        // Bit rate = MCU_CLOCK / (CPSDVSR * (1 + SCR)), we keep the prescaler at its
+        // minimum and round SCR up so that we never exceed the requested rate.
+        let rate = cmp::min(cmp::max(self.rate.get(), 1), MCU_CLOCK / 2);
+        let divisor = (MCU_CLOCK + (2 * rate) - 1) / (2 * rate);
+        let scr = cmp::min(cmp::max(divisor, 1) - 1, 0xFF);
+        regs.cpsr.write(ClockPrescale::CPSDVSR.val(2));
+
+        let polarity = match self.polarity.get() {
+            ClockPolarity::IdleLow => Control0::SPO::CLEAR,
+            ClockPolarity::IdleHigh => Control0::SPO::SET,
+        };
+        let phase = match self.phase.get() {
+            ClockPhase::SampleLeading => Control0::SPH::CLEAR,
+            ClockPhase::SampleTrailing => Control0::SPH::SET,
+        };
+        regs.cr0.write(
+            Control0::SCR.val(scr) + polarity + phase + Control0::FRF::MotorolaSpi
+                + Control0::DSS::Len8,
+        );
+
+        // Enable as master
+        regs.cr1.write(Control1::MS::CLEAR + Control1::SSE::SET);
+    }
+
+    /// The actual bit rate given the current divisor setting.
+    fn actual_rate(&self) -> u32 {
+        let regs = unsafe { &*self.regs };
+        MCU_CLOCK / (2 * (regs.cr0.read(Control0::SCR) + 1))
+    }
+
+    pub fn disable(&self) {
+        let regs = unsafe { &*self.regs };
+        regs.cr1.modify(Control1::SSE::CLEAR);
+    }
+
+    pub fn disable_interrupts(&self) {
+        let regs = unsafe { &*self.regs };
+        regs.imsc.set(0);
+        regs.icr.write(Interrupts::RT::SET + Interrupts::ROR::SET);
+    }
+
+    fn select(&self) {
+        self.chip_select.get().map(|cs| unsafe {
+            gpio::PORT[cs as usize].clear();
+        });
+    }
+
+    fn deselect(&self) {
+        self.chip_select.get().map(|cs| unsafe {
+            gpio::PORT[cs as usize].set();
+        });
+    }
+
+    fn hw_busy(&self) -> bool {
+        let regs = unsafe { &*self.regs };
+        regs.sr.is_set(Status::BSY)
+    }
+
+    /// Push bytes into the TX FIFO without letting more than a FIFO's worth of
+    /// data be outstanding, which would overrun the RX FIFO.
+    fn fill_tx_fifo(&self) {
+        let regs = unsafe { &*self.regs };
+        self.tx_buffer.map(|buf| {
+            while self.tx_index.get() < self.transfer_len.get()
+                && self.tx_index.get() - self.rx_index.get() < FIFO_DEPTH
+                && regs.sr.is_set(Status::TNF)
+            {
+                regs.dr.set(buf[self.tx_index.get()] as u32);
+                self.tx_index.set(self.tx_index.get() + 1);
+            }
+        });
+    }
+
+    fn drain_rx_fifo(&self) {
+        let regs = unsafe { &*self.regs };
+        while regs.sr.is_set(Status::RNE) && self.rx_index.get() < self.transfer_len.get() {
+            let byte = regs.dr.get() as u8;
+            let index = self.rx_index.get();
+            self.rx_buffer.map(|buf| buf[index] = byte);
+            self.rx_index.set(index + 1);
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = unsafe { &*self.regs };
+        // Clear the timeout and overrun interrupts, the others clear with the FIFO level
+        regs.icr.write(Interrupts::RT::SET + Interrupts::ROR::SET);
+
+        if !self.busy.get() {
+            return;
+        }
+
+        self.drain_rx_fifo();
+
+        if self.rx_index.get() < self.transfer_len.get() {
+            self.fill_tx_fifo();
+            return;
+        }
+
+        // The transfer is complete
+        regs.imsc.set(0);
+        self.busy.set(false);
+        if !self.hold_low.get() {
+            self.deselect();
+        }
+
+        let len = self.transfer_len.get();
+        let rx_buffer = self.rx_buffer.take();
+        self.tx_buffer.take().map(move |tx_buffer| {
+            self.client
+                .get()
+                .map(move |client| client.read_write_done(tx_buffer, rx_buffer, len));
+        });
+    }
+}
+
+impl spi::SpiMaster for SSI {
+    type ChipSelect = u8;
+
+    fn set_client(&self, client: &'static SpiMasterClient) {
+        self.client.set(Some(client));
+    }
+
+    fn init(&self) {
+        if !self.enabled.get() {
+            unsafe {
+                PM.request_resource(prcm::PowerDomain::Serial as u32);
+            }
+            prcm::Clock::enable_ssi();
+            self.enabled.set(true);
+        }
+
+        self.configure();
+    }
+
+    fn is_busy(&self) -> bool {
+        self.busy.get()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        let mut count = cmp::min(len, write_buffer.len());
+        read_buffer
+            .as_ref()
+            .map(|buf| count = cmp::min(count, buf.len()));
+        if count == 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        self.busy.set(true);
+        self.transfer_len.set(count);
+        self.tx_index.set(0);
+        self.rx_index.set(0);
+        self.tx_buffer.replace(write_buffer);
+        self.rx_buffer.put(read_buffer);
+
+        self.select();
+        self.fill_tx_fifo();
+
+        let regs = unsafe { &*self.regs };
+        regs.imsc.write(Interrupts::RX::SET + Interrupts::RT::SET);
+
+        ReturnCode::SUCCESS
+    }
+
+    fn write_byte(&self, val: u8) {
+        self.read_write_byte(val);
+    }
+
+    fn read_byte(&self) -> u8 {
+        self.read_write_byte(0)
+    }
+
+    fn read_write_byte(&self, val: u8) -> u8 {
+        let regs = unsafe { &*self.regs };
+        // Wait for space in the FIFO
+        while !regs.sr.is_set(Status::TNF) {}
+        regs.dr.set(val as u32);
+        // Every byte sent clocks in a byte
+        while !regs.sr.is_set(Status::RNE) {}
+        regs.dr.get() as u8
+    }
+
+    fn specify_chip_select(&self, cs: Self::ChipSelect) {
+        pins::claim(cs as usize, "SSI0 CS");
+        self.chip_select.set(Some(cs));
+    }
+
+    fn set_rate(&self, rate: u32) -> u32 {
+        self.rate.set(rate);
+        if self.enabled.get() {
+            self.set_format();
+        }
+        self.get_rate()
+    }
+
+    fn get_rate(&self) -> u32 {
+        if self.enabled.get() {
+            self.actual_rate()
+        } else {
+            self.rate.get()
+        }
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.polarity.set(polarity);
+        if self.enabled.get() {
+            self.set_format();
+        }
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.phase.set(phase);
+        if self.enabled.get() {
+            self.set_format();
+        }
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    /// Keep the chip select asserted until `release_low` is called, also
+    /// in between transfers.
+    fn hold_low(&self) {
+        self.hold_low.set(true);
+        self.select();
+    }
+
+    fn release_low(&self) {
+        self.hold_low.set(false);
+        if !self.busy.get() {
+            self.deselect();
+        }
+    }
+}
+
+impl peripheral_manager::PowerClient for SSI {
+    fn before_sleep(&self, _sleep_mode: u32) {
+        if !self.enabled.get() {
+            return;
+        }
+
+        // Wait for the last frame to be shifted out
+        while self.hw_busy() {}
+
+        unsafe {
+            // Disable the data pins in order to avoid current leakage
+            self.mosi_pin.get().map(|pin| {
+                gpio::PORT[pin as usize].disable();
+            });
+            self.clk_pin.get().map(|pin| {
+                gpio::PORT[pin as usize].disable();
+            });
+
+            PM.release_resource(prcm::PowerDomain::Serial as u32);
+        }
+
+        prcm::Clock::disable_ssi();
+    }
+
+    fn after_wakeup(&self, _sleep_mode: u32) {
+        if !self.enabled.get() {
+            return;
+        }
+
+        unsafe {
+            PM.request_resource(prcm::PowerDomain::Serial as u32);
+        }
+        prcm::Clock::enable_ssi();
+        self.configure();
+    }
+
+    fn lowest_sleep_mode(&self) -> u32 {
+        if self.busy.get() {
+            chip::SleepMode::Sleep as u32
+        } else {
+            chip::SleepMode::DeepSleep as u32
+        }
+    }
+}
@@ -1,21 +1,59 @@
+//! General Purpose Timers (GPT)
+//!
+//! Each of the four timers is used either as a single 32-bit timer, or split into
+//! two independent 16-bit halves (A and B) with an 8-bit prescaler each. Both can
+//! run in one-shot or periodic mode and report timeouts to a `TimerClient`.
+//!
+//! A timer also implements the Tock time HILs: it then counts up freely as a 32-bit
+//! timer at the MCU clock and alarms use the match interrupt of timer A. This gives
+//! a high resolution time base next to the RTC, which wraps every ~89 seconds.
+//!
+//! A half of a split timer can also generate a PWM signal, which is routed to a pin
+//! through the IOC port event of that half. It implements the Tock PWM HIL, with the
+//! prescaler extending the 16-bit counter to 24 bits.
+//!
+//! The same port event feeds the capture input of the half, so a half can also
+//! timestamp the edges on any DIO (edge-time mode), or count them and report once a
+//! number of edges has been seen (edge-count mode). Results go to a `CaptureClient`.
+//!
+//! The timers are clocked from the MCU clock and stop in deep sleep, so a running
+//! timer or a PWM output keeps the chip in Sleep. So does a timer with an alarm
+//! client, whose counter is a time base that must not restart after deep sleep.
+//!
+//! For details see the general purpose timers chapter in the cc2650 technical
+//! reference manual.
+
 use core::cell::Cell;
 use kernel::common::regs::{ReadOnly, ReadWrite};
+use kernel::hil::gpio::Pin;
+use kernel::hil::pwm;
+use kernel::hil::time::{self, Alarm, Frequency, Time};
+use kernel::ReturnCode;
+
+use cc26xx::peripheral_interrupts;
+use chip;
+use chip::SleepMode;
+use gpio;
+use ioc;
+use peripheral_manager::PowerClient;
+use pins;
+use power::PM;
 use prcm;
 
 #[repr(C)]
 pub struct Registers {
     pub cfg: ReadWrite<u32, Configuration::Register>,
-    pub tamr: ReadWrite<u32, TimerAMode::Register>,
-    pub tbmr: ReadWrite<u32>,
+    pub tamr: ReadWrite<u32, TimerMode::Register>,
+    pub tbmr: ReadWrite<u32, TimerMode::Register>,
     pub ctl: ReadWrite<u32, Control::Register>,
     pub sync: ReadWrite<u32>,
 
     _reserved0: [u8; 0x4],
 
-    pub imr: ReadWrite<u32, InterruptMask::Register>,
-    pub ris: ReadOnly<u32>,
-    pub mis: ReadOnly<u32, MaskedInterruptStatus::Register>,
-    pub iclr: ReadWrite<u32, InterruptClear::Register>,
+    pub imr: ReadWrite<u32, Interrupts::Register>,
+    pub ris: ReadOnly<u32, Interrupts::Register>,
+    pub mis: ReadOnly<u32, Interrupts::Register>,
+    pub iclr: ReadWrite<u32, Interrupts::Register>,
     pub tailr: ReadWrite<u32>,
     pub tbilr: ReadWrite<u32>,
     pub tamatchr: ReadWrite<u32>,
@@ -47,39 +85,62 @@ register_bitfields![
     u32,
     Control [
         TAEN OFFSET(0) NUMBITS(1) [],
-        TBEN OFFSET(8) NUMBITS(1) []
+        // Edges captured in the capture modes
+        TAEVENT OFFSET(2) NUMBITS(2) [
+            Rising = 0x0,
+            Falling = 0x1,
+            Both = 0x3
+        ],
+        // Invert the PWM output
+        TAPWML OFFSET(6) NUMBITS(1) [],
+        TBEN OFFSET(8) NUMBITS(1) [],
+        TBEVENT OFFSET(10) NUMBITS(2) [
+            Rising = 0x0,
+            Falling = 0x1,
+            Both = 0x3
+        ],
+        TBPWML OFFSET(14) NUMBITS(1) []
     ],
     Configuration [
         CFG OFFSET(0) NUMBITS(3) [
-            timer32Bit = 0x0,
-            timer64Bit = 0x4
+            Timer32Bit = 0x0,
+            Timer16Bit = 0x4
         ]
     ],
-    TimerAMode [
-        TAMR OFFSET(0) NUMBITS(2) [
+    // Shared by timer A (TAMR) and timer B (TBMR)
+    TimerMode [
+        // Match interrupt enable
+        TMIE OFFSET(5) NUMBITS(1) [],
+        // Count direction
+        TCDIR OFFSET(4) NUMBITS(1) [
+            Down = 0,
+            Up = 1
+        ],
+        // Alternate mode select, PWM when set
+        TAMS OFFSET(3) NUMBITS(1) [],
+        // Capture mode
+        TCM OFFSET(2) NUMBITS(1) [
+            EdgeCount = 0,
+            EdgeTime = 1
+        ],
+        TMR OFFSET(0) NUMBITS(2) [
             OneShot = 0x1,
             Periodic = 0x2,
             Capture = 0x3
         ]
     ],
-    InterruptMask [
-        TATOIM OFFSET(0) NUMBITS(1) [],
-        TBTOIM OFFSET(8) NUMBITS(1) []
-    ],
-    MaskedInterruptStatus [
-        TATOMIS OFFSET(0) NUMBITS(1) [],
-        TBTOMIS OFFSET(8) NUMBITS(1) []
-    ],
-    InterruptClear [
-        TATOCINT OFFSET(0) NUMBITS(1) [],
-        TBTOCINT OFFSET(8) NUMBITS(1) []
+    Interrupts [
+        TBMIM OFFSET(11) NUMBITS(1) [],
+        CBEIM OFFSET(10) NUMBITS(1) [],
+        CBMIM OFFSET(9) NUMBITS(1) [],
+        TBTOIM OFFSET(8) NUMBITS(1) [],
+        TAMIM OFFSET(4) NUMBITS(1) [],
+        CAEIM OFFSET(2) NUMBITS(1) [],
+        CAMIM OFFSET(1) NUMBITS(1) [],
+        TATOIM OFFSET(0) NUMBITS(1) []
     ]
 ];
 
-pub const GPT_CFG_32_BIT: u32 = 0x0;
-pub const GPT_ONE_SHOT: u32 = 0x1;
-pub const GPT_REG_BIT: u32 = 0x1;
-
 #[derive(Copy, Clone, PartialEq)]
 pub enum TimerBase {
     GPT0 = 0x4001_0000,
@@ -93,55 +154,641 @@ pub static mut GPT1: Timer = Timer::new(TimerBase::GPT1);
 pub static mut GPT2: Timer = Timer::new(TimerBase::GPT2);
 pub static mut GPT3: Timer = Timer::new(TimerBase::GPT3);
 
-pub struct Timer {
-    regs: *const Registers,
-    client: Cell<Option<&'static TimerClient>>,
+pub const MCU_CLOCK: u32 = 48_000_000;
+
+// In PWM mode the prescaler holds bits 23:16 of the load and match values
+const PWM_MAX_PERIOD: u32 = 1 << 24;
+const PWM_MAX_DUTY_CYCLE: usize = 0xFFFF;
+
+// Captured edge times count up through the 24 bits of the counter and prescaler
+const CAPTURE_MASK: u32 = 0xFF_FFFF;
+
+// Alarms up to this far behind the counter are due, like `PAST_WINDOW` of the RTC
+const ALARM_PAST_WINDOW: u32 = MCU_CLOCK;
+
+/// One of the halves of a split timer, the 32-bit timer uses `A`.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Half {
+    A,
+    B,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mode {
+    OneShot,
+    Periodic,
 }
 
-trait TimerClient {
-    fn fired(&self);
+/// The edges of the input which are captured or counted.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
 }
 
-pub fn power_on_timers() {
-    prcm::Power::enable_domain(prcm::PowerDomain::Serial);
-    while !prcm::Power::is_enabled(prcm::PowerDomain::Serial) {}
-    prcm::Clock::enable_gpt();
+#[derive(Copy, Clone, PartialEq)]
+enum Config {
+    None,
+    Timeout,
+    Split,
+    Alarm,
+}
+
+/// A PWM output: a half of a timer and the DIO its signal is routed to.
+pub struct PwmPin {
+    half: Half,
+    dio: u8,
+}
+
+impl PwmPin {
+    /// The PWM output of `half` on `dio`, claiming the pin.
+    pub fn new(half: Half, dio: u8) -> PwmPin {
+        pins::claim(dio as usize, "GPT PWM");
+        PwmPin { half: half, dio: dio }
+    }
+}
+
+pub trait TimerClient {
+    fn fired(&self, half: Half);
+}
+
+pub trait CaptureClient {
+    /// An edge was seen at `time`, in MCU clock cycles. The time wraps at 2^24, so
+    /// pulse widths and periods are the difference of two captures modulo 2^24.
+    fn captured(&self, half: Half, time: u32);
+
+    /// The number of edges passed to `capture_count` has been counted.
+    fn counted(&self, half: Half);
+}
+
+pub struct Freq48MHz(());
+
+impl Frequency for Freq48MHz {
+    fn frequency() -> u32 {
+        MCU_CLOCK
+    }
+}
+
+pub struct Timer {
+    regs: *const Registers,
+    index: u32,
+    client: Cell<Option<&'static TimerClient>>,
+    alarm_client: Cell<Option<&'static time::Client>>,
+    capture_client: Cell<Option<&'static CaptureClient>>,
+    enabled: Cell<bool>,
+    config: Cell<Config>,
+    mode_a: Cell<Mode>,
+    mode_b: Cell<Mode>,
+    running_a: Cell<bool>,
+    running_b: Cell<bool>,
+    armed: Cell<bool>,
+    // The alarm was already due when it was set, its interrupt is pended in software
+    alarm_due: Cell<bool>,
 }
 
 impl Timer {
     const fn new(gpt_base: TimerBase) -> Timer {
         Timer {
             regs: (gpt_base as u32) as *const Registers,
+            // The timers are 4KB apart, which gives their index for clock gating
+            index: ((gpt_base as u32) - (TimerBase::GPT0 as u32)) >> 12,
             client: Cell::new(None),
+            alarm_client: Cell::new(None),
+            capture_client: Cell::new(None),
+            enabled: Cell::new(false),
+            config: Cell::new(Config::None),
+            mode_a: Cell::new(Mode::OneShot),
+            mode_b: Cell::new(Mode::OneShot),
+            running_a: Cell::new(false),
+            running_b: Cell::new(false),
+            armed: Cell::new(false),
+            alarm_due: Cell::new(false),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static TimerClient) {
+        self.client.set(Some(client));
+    }
+
+    pub fn set_alarm_client(&self, client: &'static time::Client) {
+        self.alarm_client.set(Some(client));
+    }
+
+    pub fn set_capture_client(&self, client: &'static CaptureClient) {
+        self.capture_client.set(Some(client));
+    }
+
+    fn enable(&self) {
+        if !self.enabled.get() {
+            unsafe {
+                PM.request_resource(prcm::PowerDomain::Peripherals as u32);
+            }
+            prcm::Clock::enable_gpt(self.index);
+            self.enabled.set(true);
+        }
+    }
+
+    fn disable(&self) {
+        if self.enabled.get() {
+            prcm::Clock::disable_gpt(self.index);
+            unsafe {
+                PM.release_resource(prcm::PowerDomain::Peripherals as u32);
+            }
+            self.enabled.set(false);
+            self.config.set(Config::None);
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        !self.running_a.get() && !self.running_b.get() && !self.armed.get()
+    }
+
+    /// Switch the timer to a configuration, only possible while nothing runs.
+    fn configure(&self, config: Config) -> ReturnCode {
+        self.enable();
+        if self.config.get() == config {
+            return ReturnCode::SUCCESS;
+        }
+        if !self.is_idle() {
+            return ReturnCode::EBUSY;
+        }
+
+        let regs: &Registers = unsafe { &*self.regs };
+
+        // Disable the timers before configuration
+        regs.ctl
+            .modify(Control::TAEN::CLEAR + Control::TBEN::CLEAR);
+        regs.imr.set(0);
+        regs.iclr.set(0xFFFF_FFFF);
+
+        match config {
+            Config::Split => regs.cfg.write(Configuration::CFG::Timer16Bit),
+            _ => regs.cfg.write(Configuration::CFG::Timer32Bit),
+        }
+        self.config.set(config);
+
+        if config == Config::Alarm {
+            // Count up freely through the whole 32-bit range
+            regs.tamr.write(
+                TimerMode::TMR::Periodic + TimerMode::TCDIR::Up + TimerMode::TMIE::SET,
+            );
+            regs.tailr.set(0xFFFF_FFFF);
+            regs.ctl.modify(Control::TAEN::SET);
+        }
+
+        ReturnCode::SUCCESS
+    }
+
+    /// Start the 32-bit timer, firing after `ticks` cycles of the MCU clock.
+    pub fn start(&self, mode: Mode, ticks: u32) -> ReturnCode {
+        let res = self.configure(Config::Timeout);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        self.start_half(Half::A, mode, ticks, 0);
+        ReturnCode::SUCCESS
+    }
+
+    /// Start one half of the split timer, firing after `ticks` periods of the MCU
+    /// clock divided by `prescaler + 1`.
+    pub fn start_split(&self, half: Half, mode: Mode, prescaler: u8, ticks: u16) -> ReturnCode {
+        let res = self.configure(Config::Split);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        self.start_half(half, mode, ticks as u32, prescaler);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_half(&self, half: Half, mode: Mode, ticks: u32, prescaler: u8) {
+        let regs: &Registers = unsafe { &*self.regs };
+        let tmr = match mode {
+            Mode::OneShot => TimerMode::TMR::OneShot,
+            Mode::Periodic => TimerMode::TMR::Periodic,
+        };
+
+        // Count down to zero, the prescaler acts as a true prescaler when counting down
+        match half {
+            Half::A => {
+                regs.ctl.modify(Control::TAEN::CLEAR);
+                regs.tamr.write(tmr + TimerMode::TCDIR::Down);
+                regs.tapr.set(prescaler as u32);
+                regs.tailr.set(ticks);
+                regs.iclr.write(Interrupts::TATOIM::SET);
+                regs.imr.modify(Interrupts::TATOIM::SET);
+                self.mode_a.set(mode);
+                self.running_a.set(true);
+                regs.ctl.modify(Control::TAEN::SET);
+            }
+            Half::B => {
+                regs.ctl.modify(Control::TBEN::CLEAR);
+                regs.tbmr.write(tmr + TimerMode::TCDIR::Down);
+                regs.tbpr.set(prescaler as u32);
+                regs.tbilr.set(ticks);
+                regs.iclr.write(Interrupts::TBTOIM::SET);
+                regs.imr.modify(Interrupts::TBTOIM::SET);
+                self.mode_b.set(mode);
+                self.running_b.set(true);
+                regs.ctl.modify(Control::TBEN::SET);
+            }
+        }
+    }
+
+    /// Stop a half of the timer, or the 32-bit timer when stopping `Half::A`.
+    pub fn stop(&self, half: Half) {
+        if self.config.get() != Config::Timeout && self.config.get() != Config::Split {
+            return;
+        }
+
+        let regs: &Registers = unsafe { &*self.regs };
+        match half {
+            Half::A => {
+                regs.ctl.modify(Control::TAEN::CLEAR);
+                regs.imr.modify(
+                    Interrupts::TATOIM::CLEAR + Interrupts::CAEIM::CLEAR + Interrupts::CAMIM::CLEAR,
+                );
+                self.running_a.set(false);
+            }
+            Half::B => {
+                regs.ctl.modify(Control::TBEN::CLEAR);
+                regs.imr.modify(
+                    Interrupts::TBTOIM::CLEAR + Interrupts::CBEIM::CLEAR + Interrupts::CBMIM::CLEAR,
+                );
+                self.running_b.set(false);
+            }
+        }
+
+        if self.is_idle() {
+            self.disable();
         }
     }
 
-    pub fn one_shot(&self, value: u32) {
+    /// Output a PWM signal with a period of `period` MCU clock cycles (up to 2^24),
+    /// which is high for `high` of them.
+    fn start_pwm(&self, half: Half, period: u32, high: u32) -> ReturnCode {
+        let res = self.configure(Config::Split);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        // The output is asserted when the counter is loaded, and deasserted once
+        // it counts down to the match value.
+        let load = period - 1;
+        let matched = load - high.min(load);
+
         let regs: &Registers = unsafe { &*self.regs };
+        let mode = TimerMode::TMR::Periodic + TimerMode::TCDIR::Down + TimerMode::TAMS::SET;
+        match half {
+            Half::A => {
+                regs.ctl
+                    .modify(Control::TAEN::CLEAR + Control::TAPWML::CLEAR);
+                regs.imr.modify(Interrupts::TATOIM::CLEAR);
+                regs.tamr.write(mode);
+                regs.tapr.set(load >> 16);
+                regs.tailr.set(load & 0xFFFF);
+                regs.tapmr.set(matched >> 16);
+                regs.tamatchr.set(matched & 0xFFFF);
+                self.mode_a.set(Mode::Periodic);
+                self.running_a.set(true);
+                regs.ctl.modify(Control::TAEN::SET);
+            }
+            Half::B => {
+                regs.ctl
+                    .modify(Control::TBEN::CLEAR + Control::TBPWML::CLEAR);
+                regs.imr.modify(Interrupts::TBTOIM::CLEAR);
+                regs.tbmr.write(mode);
+                regs.tbpr.set(load >> 16);
+                regs.tbilr.set(load & 0xFFFF);
+                regs.tbpmr.set(matched >> 16);
+                regs.tbmatchr.set(matched & 0xFFFF);
+                self.mode_b.set(Mode::Periodic);
+                self.running_b.set(true);
+                regs.ctl.modify(Control::TBEN::SET);
+            }
+        }
 
-        // Disable timer before configuration
-        regs.ctl.modify(Control::TAEN::CLEAR);
-        regs.cfg.write(Configuration::CFG::timer64Bit);
+        ReturnCode::SUCCESS
+    }
 
-        // Set type and initial timer value
-        regs.tamr.write(TimerAMode::TAMR::OneShot);
-        regs.tailr.set(value);
+    /// Timestamp every `edge` on `dio` until the half is stopped, see
+    /// `CaptureClient::captured`.
+    pub fn capture_time(&self, half: Half, dio: u8, edge: Edge) -> ReturnCode {
+        let res = self.configure(Config::Split);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
 
-        // Enable interrupts and start the timer
-        regs.imr.write(InterruptMask::TATOIM::SET);
-        regs.ctl.modify(Control::TAEN::SET);
+        // Count up freely, with the prescaler as the upper 8 bits of the count
+        self.start_capture(half, dio, edge, true, CAPTURE_MASK);
+        ReturnCode::SUCCESS
     }
 
-    pub fn has_fired(&self) -> bool {
+    /// Count `edges` occurrences of `edge` on `dio`, then stop and notify
+    /// `CaptureClient::counted`.
+    pub fn capture_count(&self, half: Half, dio: u8, edge: Edge, edges: u16) -> ReturnCode {
+        if edges == 0 {
+            return ReturnCode::EINVAL;
+        }
+        let res = self.configure(Config::Split);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        self.start_capture(half, dio, edge, false, edges as u32);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_capture(
+        &self,
+        half: Half,
+        dio: u8,
+        edge: Edge,
+        edge_time: bool,
+        matched: u32,
+    ) {
         let regs: &Registers = unsafe { &*self.regs };
-        regs.mis.is_set(MaskedInterruptStatus::TATOMIS)
+        let cm = if edge_time {
+            TimerMode::TCM::EdgeTime
+        } else {
+            TimerMode::TCM::EdgeCount
+        };
+        let mode = TimerMode::TMR::Capture + TimerMode::TCDIR::Up + cm;
+
+        pins::claim(dio as usize, "GPT capture");
+        unsafe {
+            let pin = &gpio::PORT[dio as usize];
+            pin.make_input();
+            pin.iocfg()
+                .configure(ioc::Config::new(self.port_event(half)).input());
+        }
+
+        match half {
+            Half::A => {
+                regs.ctl.modify(Control::TAEN::CLEAR);
+                regs.tamr.write(mode);
+                regs.ctl.modify(match edge {
+                    Edge::Rising => Control::TAEVENT::Rising,
+                    Edge::Falling => Control::TAEVENT::Falling,
+                    Edge::Both => Control::TAEVENT::Both,
+                });
+                regs.tapr.set(0xFF);
+                regs.tailr.set(0xFFFF);
+                regs.tapmr.set(matched >> 16);
+                regs.tamatchr.set(matched & 0xFFFF);
+                // Start counting from zero
+                regs.tav.set(0);
+                regs.iclr
+                    .write(Interrupts::CAEIM::SET + Interrupts::CAMIM::SET);
+                regs.imr.modify(if edge_time {
+                    Interrupts::CAEIM::SET
+                } else {
+                    Interrupts::CAMIM::SET
+                });
+                self.running_a.set(true);
+                regs.ctl.modify(Control::TAEN::SET);
+            }
+            Half::B => {
+                regs.ctl.modify(Control::TBEN::CLEAR);
+                regs.tbmr.write(mode);
+                regs.ctl.modify(match edge {
+                    Edge::Rising => Control::TBEVENT::Rising,
+                    Edge::Falling => Control::TBEVENT::Falling,
+                    Edge::Both => Control::TBEVENT::Both,
+                });
+                regs.tbpr.set(0xFF);
+                regs.tbilr.set(0xFFFF);
+                regs.tbpmr.set(matched >> 16);
+                regs.tbmatchr.set(matched & 0xFFFF);
+                regs.tbv.set(0);
+                regs.iclr
+                    .write(Interrupts::CBEIM::SET + Interrupts::CBMIM::SET);
+                regs.imr.modify(if edge_time {
+                    Interrupts::CBEIM::SET
+                } else {
+                    Interrupts::CBMIM::SET
+                });
+                self.running_b.set(true);
+                regs.ctl.modify(Control::TBEN::SET);
+            }
+        }
     }
 
-    pub fn handle_interrupt(&self) {
+    /// The IOC port event carrying the PWM output and the capture input of a half.
+    fn port_event(&self, half: Half) -> ioc::PortId {
+        let half = match half {
+            Half::A => 0,
+            Half::B => 1,
+        };
+        ioc::PortId::PortEvent((self.index * 2 + half) as u8)
+    }
+
+    pub fn is_running(&self, half: Half) -> bool {
+        match half {
+            Half::A => self.running_a.get(),
+            Half::B => self.running_b.get(),
+        }
+    }
+
+    /// Current value of a half, or of the 32-bit timer for `Half::A`.
+    pub fn value(&self, half: Half) -> u32 {
+        if !self.enabled.get() {
+            return 0;
+        }
+
+        let regs: &Registers = unsafe { &*self.regs };
+        match half {
+            Half::A => regs.tar.get(),
+            Half::B => regs.tbr.get(),
+        }
+    }
+
+    pub fn handle_interrupt(&self, half: Half) {
+        let regs: &Registers = unsafe { &*self.regs };
+
+        match half {
+            Half::A => {
+                if regs.mis.is_set(Interrupts::TAMIM) || self.alarm_due.get() {
+                    self.alarm_due.set(false);
+                    regs.iclr.write(Interrupts::TAMIM::SET);
+                    regs.imr.modify(Interrupts::TAMIM::CLEAR);
+                    self.armed.set(false);
+                    self.alarm_client.get().map(|client| client.fired());
+                }
+                if regs.mis.is_set(Interrupts::TATOIM) {
+                    regs.iclr.write(Interrupts::TATOIM::SET);
+                    if self.mode_a.get() == Mode::OneShot {
+                        self.running_a.set(false);
+                    }
+                    self.client.get().map(|client| client.fired(Half::A));
+                }
+                if regs.mis.is_set(Interrupts::CAEIM) {
+                    regs.iclr.write(Interrupts::CAEIM::SET);
+                    let time = regs.tar.get() & CAPTURE_MASK;
+                    self.capture_client
+                        .get()
+                        .map(|client| client.captured(Half::A, time));
+                }
+                if regs.mis.is_set(Interrupts::CAMIM) {
+                    // The timer disables itself once the match value is reached
+                    regs.iclr.write(Interrupts::CAMIM::SET);
+                    regs.imr.modify(Interrupts::CAMIM::CLEAR);
+                    self.running_a.set(false);
+                    self.capture_client
+                        .get()
+                        .map(|client| client.counted(Half::A));
+                }
+            }
+            Half::B => {
+                if regs.mis.is_set(Interrupts::TBTOIM) {
+                    regs.iclr.write(Interrupts::TBTOIM::SET);
+                    if self.mode_b.get() == Mode::OneShot {
+                        self.running_b.set(false);
+                    }
+                    self.client.get().map(|client| client.fired(Half::B));
+                }
+                if regs.mis.is_set(Interrupts::CBEIM) {
+                    regs.iclr.write(Interrupts::CBEIM::SET);
+                    let time = regs.tbr.get() & CAPTURE_MASK;
+                    self.capture_client
+                        .get()
+                        .map(|client| client.captured(Half::B, time));
+                }
+                if regs.mis.is_set(Interrupts::CBMIM) {
+                    regs.iclr.write(Interrupts::CBMIM::SET);
+                    regs.imr.modify(Interrupts::CBMIM::CLEAR);
+                    self.running_b.set(false);
+                    self.capture_client
+                        .get()
+                        .map(|client| client.counted(Half::B));
+                }
+            }
+        }
+    }
+}
+
+impl Time for Timer {
+    type Frequency = Freq48MHz;
+
+    fn disable(&self) {
+        if self.config.get() != Config::Alarm {
+            return;
+        }
+
         let regs: &Registers = unsafe { &*self.regs };
-        regs.iclr.modify(InterruptClear::TATOCINT::SET);
-        self.client.get().map(|client| {
-            client.fired();
-        });
+        regs.imr.modify(Interrupts::TAMIM::CLEAR);
+        regs.iclr.write(Interrupts::TAMIM::SET);
+        self.armed.set(false);
+        self.alarm_due.set(false);
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+}
+
+impl Alarm for Timer {
+    fn now(&self) -> u32 {
+        if self.configure(Config::Alarm) != ReturnCode::SUCCESS {
+            return 0;
+        }
+
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.tar.get()
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        if self.configure(Config::Alarm) != ReturnCode::SUCCESS {
+            return;
+        }
+
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.tamatchr.set(tics);
+        regs.iclr.write(Interrupts::TAMIM::SET);
+        regs.imr.modify(Interrupts::TAMIM::SET);
+        self.armed.set(true);
+
+        // At 48MHz a close alarm may be behind the counter by now, the match would
+        // only come after the counter wraps (~89s). Fire it from the interrupt.
+        if regs.tar.get().wrapping_sub(tics) <= ALARM_PAST_WINDOW {
+            self.alarm_due.set(true);
+            // The A and B interrupts of the timers are numbered in turn
+            chip::pend_interrupt(peripheral_interrupts::GPT0A + 2 * self.index);
+        }
+    }
+
+    fn get_alarm(&self) -> u32 {
+        let regs: &Registers = unsafe { &*self.regs };
+        regs.tamatchr.get()
+    }
+}
+
+impl pwm::Pwm for Timer {
+    type Pin = PwmPin;
+
+    fn start(&self, pin: &PwmPin, frequency_hz: usize, duty_cycle: usize) -> ReturnCode {
+        if frequency_hz == 0 || frequency_hz > self.get_maximum_frequency_hz() {
+            return ReturnCode::EINVAL;
+        }
+        let period = MCU_CLOCK / frequency_hz as u32;
+        if period > PWM_MAX_PERIOD {
+            return ReturnCode::EINVAL;
+        }
+
+        let duty_cycle = duty_cycle.min(PWM_MAX_DUTY_CYCLE) as u64;
+        let high = (period as u64 * duty_cycle / PWM_MAX_DUTY_CYCLE as u64) as u32;
+
+        let res = self.start_pwm(pin.half, period, high);
+        if res == ReturnCode::SUCCESS {
+            // The pin is driven low as a GPIO whenever the output is stopped
+            let gpio_pin = unsafe { &gpio::PORT[pin.dio as usize] };
+            gpio_pin.make_output();
+            gpio_pin.clear();
+            gpio_pin
+                .iocfg()
+                .configure(ioc::Config::new(self.port_event(pin.half)));
+        }
+        res
+    }
+
+    fn stop(&self, pin: &PwmPin) -> ReturnCode {
+        if !self.is_running(pin.half) {
+            return ReturnCode::EOFF;
+        }
+
+        // Hand the pin back to the GPIO module before the output stops
+        unsafe {
+            gpio::PORT[pin.dio as usize].make_output();
+        }
+        Timer::stop(self, pin.half);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_maximum_frequency_hz(&self) -> usize {
+        (MCU_CLOCK / 2) as usize
+    }
+
+    fn get_maximum_duty_cycle(&self) -> usize {
+        PWM_MAX_DUTY_CYCLE
+    }
+}
+
+impl PowerClient for Timer {
+    fn before_sleep(&self, _sleep_mode: u32) {
+        // Only reached when nothing runs, the timer is reconfigured on the next use
+        self.disable();
+    }
+
+    fn after_wakeup(&self, _sleep_mode: u32) {}
+
+    fn lowest_sleep_mode(&self) -> u32 {
+        // Alarm clients keep the counter as their time base, it can't restart
+        if self.is_idle() && self.alarm_client.get().is_none() {
+            SleepMode::DeepSleep as u32
+        } else {
+            SleepMode::Sleep as u32
+        }
     }
 }
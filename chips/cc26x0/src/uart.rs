@@ -1,13 +1,40 @@
 //! UART driver, cc26xx family
+//!
+//! Transmit and receive are driven by the UART interrupts. A transmission fills
+//! the TX FIFO and refills it whenever it drains to its trigger level. The TX
+//! interrupt only fires when the FIFO level passes through the trigger level, so
+//! a transmission that fits into the FIFO at once completes from a UART interrupt
+//! pended in software instead. Received
+//! bytes are taken from the RX FIFO when it fills to its trigger level, and once
+//! the line has been idle for 32 bit periods (the RX timeout). The timeout ends a
+//! receive early, with only the bytes received so far, so reads of unknown length
+//! (like a console line) complete.
+//!
+//! The frame format follows `UARTParams`, the word length and stick parity, which
+//! the HIL has no parameters for, are set with `set_word_length` and
+//! `set_stick_parity` before `init`. Hardware flow control needs the CTS and RTS
+//! pins, set with `set_flow_control_pins`.
+//!
+//! Line errors end a receive early, with the bytes received intact so far and the
+//! error. A break is reported as a framing error, since it is one.
+//!
+//! `send_byte` and `read_byte` block instead, for the panic handler and the
+//! serial loader, which run without interrupts.
+//!
+//! For details see the UART chapter in the cc2650 technical reference manual.
 use kernel::common::regs::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::take_cell::TakeCell;
 use kernel::hil::gpio::Pin;
 use kernel::hil::uart;
 use core::cell::Cell;
+use core::cmp;
 use kernel;
 
 use prcm;
 use cc26xx::gpio;
+use cc26xx::peripheral_interrupts;
 use ioc;
+use pins;
 use power::PM;
 use chip;
 
@@ -16,10 +43,15 @@ use peripheral_manager;
 pub const UART_BASE: usize = 0x4000_1000;
 pub const MCU_CLOCK: u32 = 48_000_000;
 
+// Bytes in each of the FIFOs
+const FIFO_DEPTH: usize = 32;
+// The RX interrupt fires with this many bytes in the RX FIFO, see `FifoLevel::RXSEL`
+const RX_TRIGGER: usize = FIFO_DEPTH / 2;
+
 #[repr(C)]
 struct Registers {
     dr: ReadWrite<u32>,
-    rsr_ecr: ReadWrite<u32>,
+    rsr_ecr: ReadWrite<u32, ReceiveStatus::Register>,
     _reserved0: [u8; 0x10],
     fr: ReadOnly<u32, Flags::Register>,
     _reserved1: [u8; 0x8],
@@ -27,7 +59,7 @@ struct Registers {
     fbrd: ReadWrite<u32, FracDivisor::Register>,
     lcrh: ReadWrite<u32, LineControl::Register>,
     ctl: ReadWrite<u32, Control::Register>,
-    ifls: ReadWrite<u32>,
+    ifls: ReadWrite<u32, FifoLevel::Register>,
     imsc: ReadWrite<u32, Interrupts::Register>,
     ris: ReadOnly<u32, Interrupts::Register>,
     mis: ReadOnly<u32, Interrupts::Register>,
@@ -37,19 +69,31 @@ struct Registers {
 
 register_bitfields![
     u32,
+    ReceiveStatus [
+        OVERRUN_ERROR OFFSET(3) NUMBITS(1) [],
+        BREAK_ERROR OFFSET(2) NUMBITS(1) [],
+        PARITY_ERROR OFFSET(1) NUMBITS(1) [],
+        FRAMING_ERROR OFFSET(0) NUMBITS(1) []
+    ],
     Control [
         UART_ENABLE OFFSET(0) NUMBITS(1) [],
         TX_ENABLE OFFSET(8) NUMBITS(1) [],
-        RX_ENABLE OFFSET(9) NUMBITS(1) []
+        RX_ENABLE OFFSET(9) NUMBITS(1) [],
+        RTS_ENABLE OFFSET(14) NUMBITS(1) [],
+        CTS_ENABLE OFFSET(15) NUMBITS(1) []
     ],
     LineControl [
+        PARITY_ENABLE OFFSET(1) NUMBITS(1) [],
+        EVEN_PARITY OFFSET(2) NUMBITS(1) [],
+        TWO_STOP_BITS OFFSET(3) NUMBITS(1) [],
         FIFO_ENABLE OFFSET(4) NUMBITS(1) [],
         WORD_LENGTH OFFSET(5) NUMBITS(2) [
             Len5 = 0x0,
             Len6 = 0x1,
             Len7 = 0x2,
             Len8 = 0x3
-        ]
+        ],
+        STICK_PARITY OFFSET(7) NUMBITS(1) []
     ],
     IntDivisor [
         DIVISOR OFFSET(0) NUMBITS(16) []
@@ -59,19 +103,58 @@ register_bitfields![
     ],
     Flags [
         TX_FIFO_FULL OFFSET(5) NUMBITS(1) [],
+        RX_FIFO_EMPTY OFFSET(4) NUMBITS(1) [],
         UART_BUSY OFFSET(3) NUMBITS(1) []
     ],
+    FifoLevel [
+        RXSEL OFFSET(3) NUMBITS(3) [
+            OneEighth = 0x0,
+            OneQuarter = 0x1,
+            Half = 0x2,
+            ThreeQuarters = 0x3,
+            SevenEighths = 0x4
+        ],
+        TXSEL OFFSET(0) NUMBITS(3) [
+            OneEighth = 0x0,
+            OneQuarter = 0x1,
+            Half = 0x2,
+            ThreeQuarters = 0x3,
+            SevenEighths = 0x4
+        ]
+    ],
     Interrupts [
-        ALL_INTERRUPTS OFFSET(0) NUMBITS(12) []
+        ALL_INTERRUPTS OFFSET(0) NUMBITS(12) [],
+        RT OFFSET(6) NUMBITS(1) [], // RX timeout
+        TX OFFSET(5) NUMBITS(1) [], // TX FIFO at its trigger level or below
+        RX OFFSET(4) NUMBITS(1) []  // RX FIFO at its trigger level or above
     ]
 ];
 
+#[derive(Copy, Clone)]
+pub enum WordLength {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
 pub struct UART {
     regs: *const Registers,
     client: Cell<Option<&'static uart::Client>>,
     tx_pin: Cell<Option<u8>>,
     rx_pin: Cell<Option<u8>>,
+    cts_pin: Cell<Option<u8>>,
+    rts_pin: Cell<Option<u8>>,
+    word_length: Cell<WordLength>,
+    stick_parity: Cell<bool>,
     params: Cell<Option<kernel::hil::uart::UARTParams>>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_index: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
 }
 
 pub static mut UART0: UART = UART::new();
@@ -83,15 +166,47 @@ impl UART {
             client: Cell::new(None),
             tx_pin: Cell::new(None),
             rx_pin: Cell::new(None),
+            cts_pin: Cell::new(None),
+            rts_pin: Cell::new(None),
+            word_length: Cell::new(WordLength::Eight),
+            stick_parity: Cell::new(false),
             params: Cell::new(None),
+
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_index: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
         }
     }
 
     pub fn set_pins(&self, tx_pin: u8, rx_pin: u8) {
+        pins::claim(tx_pin as usize, "UART0 TX");
+        pins::claim(rx_pin as usize, "UART0 RX");
         self.tx_pin.set(Some(tx_pin));
         self.rx_pin.set(Some(rx_pin));
     }
 
+    /// The pins used for hardware flow control, when `UARTParams` asks for it.
+    pub fn set_flow_control_pins(&self, cts_pin: u8, rts_pin: u8) {
+        pins::claim(cts_pin as usize, "UART0 CTS");
+        pins::claim(rts_pin as usize, "UART0 RTS");
+        self.cts_pin.set(Some(cts_pin));
+        self.rts_pin.set(Some(rts_pin));
+    }
+
+    /// The data bits of a frame, 8 by default.
+    pub fn set_word_length(&self, word_length: WordLength) {
+        self.word_length.set(word_length);
+    }
+
+    /// Send a fixed parity bit, 1 (mark) for `Parity::Odd` and 0 (space) for
+    /// `Parity::Even`, and check received ones against it.
+    pub fn set_stick_parity(&self, stick_parity: bool) {
+        self.stick_parity.set(stick_parity);
+    }
+
     pub fn configure(&self) {
         let tx_pin = match self.tx_pin.get() {
             Some(pin) => pin,
@@ -114,8 +229,18 @@ impl UART {
             gpio::PORT[tx_pin as usize].set();
 
             // Map UART signals to IO pin
-            ioc::IOCFG[tx_pin as usize].enable_uart_tx();
-            ioc::IOCFG[rx_pin as usize].enable_uart_rx();
+            ioc::IOCFG[tx_pin as usize].configure(ioc::Config::new(ioc::PortId::Uart0Tx));
+            ioc::IOCFG[rx_pin as usize].configure(ioc::Config::new(ioc::PortId::Uart0Rx).input());
+
+            if params.hw_flow_control {
+                let (cts_pin, rts_pin) = match (self.cts_pin.get(), self.rts_pin.get()) {
+                    (Some(cts_pin), Some(rts_pin)) => (cts_pin, rts_pin),
+                    _ => panic!("Flow control pins not configured for UART"),
+                };
+                ioc::IOCFG[cts_pin as usize]
+                    .configure(ioc::Config::new(ioc::PortId::Uart0Cts).input());
+                ioc::IOCFG[rts_pin as usize].configure(ioc::Config::new(ioc::PortId::Uart0Rts));
+            }
         }
 
         // Disable the UART before configuring
@@ -123,15 +248,47 @@ impl UART {
 
         self.set_baud_rate(params.baud_rate);
 
-        // Set word length
+        // Set the frame format
         let regs = unsafe { &*self.regs };
-        regs.lcrh.write(LineControl::WORD_LENGTH::Len8);
+        let word_length = match self.word_length.get() {
+            WordLength::Five => LineControl::WORD_LENGTH::Len5,
+            WordLength::Six => LineControl::WORD_LENGTH::Len6,
+            WordLength::Seven => LineControl::WORD_LENGTH::Len7,
+            WordLength::Eight => LineControl::WORD_LENGTH::Len8,
+        };
+        let stop_bits = match params.stop_bits {
+            uart::StopBits::One => LineControl::TWO_STOP_BITS::CLEAR,
+            uart::StopBits::Two => LineControl::TWO_STOP_BITS::SET,
+        };
+        let parity = match params.parity {
+            uart::Parity::None => LineControl::PARITY_ENABLE::CLEAR,
+            uart::Parity::Odd => LineControl::PARITY_ENABLE::SET + LineControl::EVEN_PARITY::CLEAR,
+            uart::Parity::Even => LineControl::PARITY_ENABLE::SET + LineControl::EVEN_PARITY::SET,
+        };
+        let stick_parity = if self.stick_parity.get() {
+            LineControl::STICK_PARITY::SET
+        } else {
+            LineControl::STICK_PARITY::CLEAR
+        };
+        regs.lcrh
+            .write(word_length + stop_bits + parity + stick_parity);
 
         self.fifo_enable();
+        // Refill the TX FIFO with 4 bytes left, take from the RX FIFO at 16 bytes
+        regs.ifls
+            .write(FifoLevel::TXSEL::OneEighth + FifoLevel::RXSEL::Half);
+
+        let flow_control = if params.hw_flow_control {
+            Control::CTS_ENABLE::SET + Control::RTS_ENABLE::SET
+        } else {
+            Control::CTS_ENABLE::CLEAR + Control::RTS_ENABLE::CLEAR
+        };
 
         // Enable UART, RX and TX
-        regs.ctl
-            .write(Control::UART_ENABLE::SET + Control::RX_ENABLE::SET + Control::TX_ENABLE::SET);
+        regs.ctl.write(
+            Control::UART_ENABLE::SET + Control::RX_ENABLE::SET + Control::TX_ENABLE::SET
+                + flow_control,
+        );
     }
 
     fn set_baud_rate(&self, baud_rate: u32) {
@@ -174,13 +331,121 @@ impl UART {
         regs.icr.write(Interrupts::ALL_INTERRUPTS::SET);
     }
 
+    fn fill_tx_fifo(&self) {
+        let regs = unsafe { &*self.regs };
+        self.tx_buffer.map(|buf| {
+            while self.tx_index.get() < self.tx_len.get() && self.tx_ready() {
+                regs.dr.set(buf[self.tx_index.get()] as u32);
+                self.tx_index.set(self.tx_index.get() + 1);
+            }
+        });
+    }
+
+    /// Move up to `max` bytes from the RX FIFO to the receive buffer, stopping at
+    /// the first line error.
+    fn drain_rx_fifo(&self, max: usize) -> Option<uart::Error> {
+        let regs = unsafe { &*self.regs };
+        let mut count = 0;
+        while count < max && self.rx_ready() && self.rx_index.get() < self.rx_len.get() {
+            let byte = regs.dr.get() as u8;
+            // The status of the byte just read, the overrun is flagged once the FIFO
+            // is full and leaves the byte intact
+            let framing_error = regs.rsr_ecr.is_set(ReceiveStatus::FRAMING_ERROR)
+                || regs.rsr_ecr.is_set(ReceiveStatus::BREAK_ERROR);
+            let parity_error = regs.rsr_ecr.is_set(ReceiveStatus::PARITY_ERROR);
+            let overrun_error = regs.rsr_ecr.is_set(ReceiveStatus::OVERRUN_ERROR);
+
+            if !framing_error && !parity_error {
+                let index = self.rx_index.get();
+                self.rx_buffer.map(|buf| buf[index] = byte);
+                self.rx_index.set(index + 1);
+                count += 1;
+            }
+
+            if framing_error || parity_error || overrun_error {
+                // Any write clears the errors
+                regs.rsr_ecr.set(0);
+                return Some(if framing_error {
+                    uart::Error::FramingError
+                } else if parity_error {
+                    uart::Error::ParityError
+                } else {
+                    uart::Error::OverrunError
+                });
+            }
+        }
+        None
+    }
+
+    /// Whether all of the transmission is in the FIFO, but not yet completed.
+    fn tx_queued(&self) -> bool {
+        self.tx_buffer.is_some() && self.tx_index.get() == self.tx_len.get()
+    }
+
+    fn handle_tx_interrupt(&self) {
+        if self.tx_index.get() < self.tx_len.get() {
+            self.fill_tx_fifo();
+        }
+        if self.tx_index.get() < self.tx_len.get() {
+            return;
+        }
+
+        // Everything is in the FIFO, the buffer is no longer needed
+        let regs = unsafe { &*self.regs };
+        regs.imsc.modify(Interrupts::TX::CLEAR);
+        self.tx_buffer.take().map(|tx_buffer| {
+            self.client.get().map(move |client| {
+                client.transmit_complete(tx_buffer, kernel::hil::uart::Error::CommandComplete);
+            });
+        });
+    }
+
+    fn handle_rx_interrupt(&self, timeout: bool) {
+        let error = if timeout {
+            // The line is idle, take everything that's left
+            self.drain_rx_fifo(FIFO_DEPTH)
+        } else {
+            // Leave a byte in the FIFO, so the timeout still fires if the line goes
+            // idle before the buffer is full
+            self.drain_rx_fifo(RX_TRIGGER - 1)
+        };
+
+        let len = self.rx_index.get();
+        if error.is_none() && len < self.rx_len.get() && !(timeout && len > 0) {
+            return;
+        }
+
+        let regs = unsafe { &*self.regs };
+        regs.imsc
+            .modify(Interrupts::RX::CLEAR + Interrupts::RT::CLEAR);
+        self.rx_buffer.take().map(|rx_buffer| {
+            self.client.get().map(move |client| {
+                client.receive_complete(
+                    rx_buffer,
+                    len,
+                    error.unwrap_or(uart::Error::CommandComplete),
+                );
+            });
+        });
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = unsafe { &*self.regs };
-        // Get status bits
-        #[allow(unused)]
-        let flags: u32 = regs.fr.get();
-        // Clear interrupts
-        regs.icr.write(Interrupts::ALL_INTERRUPTS::SET);
+        let tx = regs.mis.is_set(Interrupts::TX);
+        let rx = regs.mis.is_set(Interrupts::RX);
+        let timeout = regs.mis.is_set(Interrupts::RT);
+        // The FIFO level interrupts clear with the level, only the timeout needs clearing
+        if timeout {
+            regs.icr.write(Interrupts::RT::SET);
+        }
+
+        // Also completes a transmission that fit into the FIFO, see `transmit`
+        if tx || self.tx_queued() {
+            self.handle_tx_interrupt();
+        }
+        if rx || timeout {
+            self.handle_rx_interrupt(timeout);
+        }
     }
 
     pub fn send_byte(&self, c: u8) {
@@ -196,6 +461,18 @@ impl UART {
         !regs.fr.is_set(Flags::TX_FIFO_FULL)
     }
 
+    pub fn rx_ready(&self) -> bool {
+        let regs = unsafe { &*self.regs };
+        !regs.fr.is_set(Flags::RX_FIFO_EMPTY)
+    }
+
+    /// Blocking read of a single byte
+    pub fn read_byte(&self) -> u8 {
+        while !self.rx_ready() {}
+        let regs = unsafe { &*self.regs };
+        regs.dr.get() as u8
+    }
+
     pub fn set_params(&self, params: kernel::hil::uart::UARTParams) {
         self.params.set(Some(params));
     }
@@ -222,17 +499,45 @@ impl kernel::hil::uart::UART for UART {
             return;
         }
 
-        for i in 0..tx_len {
-            self.send_byte(tx_data[i]);
+        if self.tx_buffer.is_some() {
+            self.client.get().map(move |client| {
+                client.transmit_complete(tx_data, kernel::hil::uart::Error::RepeatCallError);
+            });
+            return;
         }
 
-        self.client.get().map(move |client| {
-            client.transmit_complete(tx_data, kernel::hil::uart::Error::CommandComplete);
-        });
+        self.tx_len.set(cmp::min(tx_len, tx_data.len()));
+        self.tx_index.set(0);
+        self.tx_buffer.replace(tx_data);
+        self.fill_tx_fifo();
+
+        if self.tx_queued() {
+            // The FIFO may never pass through the trigger level, so no TX interrupt
+            // is coming. Deliver the completion from the UART interrupt handler
+            // rather than from within this call.
+            chip::pend_interrupt(peripheral_interrupts::UART0);
+        } else {
+            let regs = unsafe { &*self.regs };
+            regs.imsc.modify(Interrupts::TX::SET);
+        }
     }
 
-    #[allow(unused)]
-    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {}
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        if self.rx_buffer.is_some() {
+            self.client.get().map(move |client| {
+                client.receive_complete(rx_buffer, 0, kernel::hil::uart::Error::RepeatCallError);
+            });
+            return;
+        }
+
+        self.rx_len.set(cmp::min(rx_len, rx_buffer.len()));
+        self.rx_index.set(0);
+        self.rx_buffer.replace(rx_buffer);
+
+        // Bytes already waiting in the FIFO raise the interrupts right away
+        let regs = unsafe { &*self.regs };
+        regs.imsc.modify(Interrupts::RX::SET + Interrupts::RT::SET);
+    }
 }
 
 impl peripheral_manager::PowerClient for UART {
@@ -248,6 +553,12 @@ impl peripheral_manager::PowerClient for UART {
             self.rx_pin.get().map(|pin| {
                 gpio::PORT[pin as usize].disable();
             });
+            self.cts_pin.get().map(|pin| {
+                gpio::PORT[pin as usize].disable();
+            });
+            self.rts_pin.get().map(|pin| {
+                gpio::PORT[pin as usize].disable();
+            });
 
             PM.release_resource(prcm::PowerDomain::Serial as u32);
         }
@@ -264,6 +575,12 @@ impl peripheral_manager::PowerClient for UART {
     }
 
     fn lowest_sleep_mode(&self) -> u32 {
-        chip::SleepMode::DeepSleep as u32
+        // The serial power domain is off in deep sleep, stay awake for the
+        // transfers in progress
+        if self.tx_buffer.is_some() || self.rx_buffer.is_some() {
+            chip::SleepMode::Sleep as u32
+        } else {
+            chip::SleepMode::DeepSleep as u32
+        }
     }
 }
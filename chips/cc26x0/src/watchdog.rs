@@ -0,0 +1,194 @@
+//! Watchdog timer (WDT)
+//!
+//! The watchdog counts down from its load value at a 32nd of the MCU clock. The
+//! first time it reaches zero it raises its interrupt and starts over, the second
+//! time (with the interrupt still pending) it resets the chip. Clearing the
+//! interrupt reloads the counter, which is how the watchdog is petted.
+//!
+//! The kernel pets the watchdog on every pass of its main loop, so a reset only
+//! happens when the kernel hangs. The warning halfway through the timeout is
+//! delivered in one of two ways:
+//!
+//! * As a regular interrupt, which wakes the chip from Sleep so the main loop gets
+//!   to pet the watchdog even while the kernel idles. A hung kernel never services
+//!   it and the chip resets.
+//! * As an NMI, which is taken even when interrupts are masked or the kernel spins
+//!   in a handler, and panics to dump the state before the reset. The chip marks
+//!   the time it idles in Sleep (`set_idle`), an NMI then pets the watchdog like
+//!   the regular interrupt does.
+//!
+//! The watchdog clock stops in deep sleep, so the watchdog is paused there. It is
+//! petted before and after deep sleep so the kernel always gets a full timeout.
+//! Once started the watchdog can only be stopped by a reset. The longest timeout is
+//! about 95 minutes, longer ones are shortened to it.
+//!
+//! For details see the watchdog timer chapter in the cc2650 technical reference
+//! manual.
+
+use core::cell::Cell;
+use kernel::common::regs::{ReadOnly, ReadWrite, WriteOnly};
+
+use chip::SleepMode;
+use peripheral_manager::PowerClient;
+
+#[repr(C)]
+struct WatchdogRegisters {
+    load: ReadWrite<u32>,
+    value: ReadOnly<u32>,
+    ctl: ReadWrite<u32, Control::Register>,
+    icr: WriteOnly<u32>,
+    _ris: ReadOnly<u32>,
+    _mis: ReadOnly<u32>,
+
+    _reserved0: [u8; 0x400],
+
+    test: ReadWrite<u32, Test::Register>,
+    _int_caus: ReadOnly<u32>,
+
+    _reserved1: [u8; 0x7E0],
+
+    lock: ReadWrite<u32>,
+}
+
+register_bitfields![
+    u32,
+    Control [
+        INTTYPE OFFSET(2) NUMBITS(1) [
+            Interrupt = 0,
+            Nmi = 1
+        ],
+        RESEN OFFSET(1) NUMBITS(1) [],
+        INTEN OFFSET(0) NUMBITS(1) []
+    ],
+    Test [
+        // Stop counting while the CPU is halted by a debugger
+        STALL OFFSET(8) NUMBITS(1) []
+    ]
+];
+
+const WATCHDOG_BASE: *const WatchdogRegisters = 0x4008_0000 as *const WatchdogRegisters;
+
+const WATCHDOG_UNLOCK: u32 = 0x1ACC_E551;
+const WATCHDOG_LOCK: u32 = 0x0;
+
+// The watchdog runs at the MCU clock divided by 32
+const WATCHDOG_TICKS_PER_MS: u32 = 48_000_000 / 32 / 1000;
+
+/// How the warning halfway through the timeout is delivered.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Warning {
+    Interrupt,
+    Nmi,
+}
+
+pub static mut WATCHDOG: Watchdog = Watchdog::new();
+
+pub struct Watchdog {
+    regs: *const WatchdogRegisters,
+    enabled: Cell<bool>,
+    timeout_ms: Cell<u32>,
+    idle: Cell<bool>,
+}
+
+impl Watchdog {
+    const fn new() -> Watchdog {
+        Watchdog {
+            regs: WATCHDOG_BASE,
+            enabled: Cell::new(false),
+            timeout_ms: Cell::new(0),
+            idle: Cell::new(false),
+        }
+    }
+
+    /// Start the watchdog, resetting the chip when it isn't petted for `timeout_ms`.
+    pub fn start(&self, timeout_ms: u32, warning: Warning) {
+        let regs: &WatchdogRegisters = unsafe { &*self.regs };
+
+        // The counter expires twice before resetting the chip
+        let load = (timeout_ms / 2)
+            .max(1)
+            .checked_mul(WATCHDOG_TICKS_PER_MS)
+            .unwrap_or(u32::max_value());
+        self.timeout_ms.set(load / WATCHDOG_TICKS_PER_MS * 2);
+        self.with_unlocked(|| {
+            regs.load.set(load);
+            regs.test.modify(Test::STALL::SET);
+            regs.ctl.write(
+                Control::INTEN::SET + Control::RESEN::SET + match warning {
+                    Warning::Interrupt => Control::INTTYPE::Interrupt,
+                    Warning::Nmi => Control::INTTYPE::Nmi,
+                },
+            );
+        });
+        self.enabled.set(true);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Reload the counter, giving the kernel a full timeout again.
+    pub fn tickle(&self) {
+        if !self.enabled.get() {
+            return;
+        }
+
+        let regs: &WatchdogRegisters = unsafe { &*self.regs };
+        self.with_unlocked(|| regs.icr.set(1));
+    }
+
+    /// The warning as a regular interrupt: the kernel is alive and just idle.
+    pub fn handle_interrupt(&self) {
+        self.tickle();
+    }
+
+    /// Whether the kernel idles in Sleep, where it is alive but doesn't pet the
+    /// watchdog until an interrupt wakes it.
+    pub fn set_idle(&self, idle: bool) {
+        self.idle.set(idle);
+    }
+
+    /// The warning as an NMI: the kernel didn't pet the watchdog in time, unless
+    /// it is just idle.
+    pub fn handle_nmi(&self) {
+        if self.idle.get() {
+            self.tickle();
+            return;
+        }
+
+        let regs: &WatchdogRegisters = unsafe { &*self.regs };
+        panic!(
+            "Watchdog not petted for {}ms, reset in {}ms (counter {:#x})",
+            self.timeout_ms.get() / 2,
+            self.timeout_ms.get() / 2,
+            regs.value.get()
+        );
+    }
+
+    fn with_unlocked<F: FnOnce()>(&self, f: F) {
+        let regs: &WatchdogRegisters = unsafe { &*self.regs };
+        regs.lock.set(WATCHDOG_UNLOCK);
+        f();
+        regs.lock.set(WATCHDOG_LOCK);
+    }
+}
+
+impl PowerClient for Watchdog {
+    fn before_sleep(&self, _sleep_mode: u32) {
+        // The counter stops in deep sleep, leave with as much time as possible
+        self.tickle();
+    }
+
+    fn after_wakeup(&self, _sleep_mode: u32) {
+        self.tickle();
+    }
+
+    fn lowest_sleep_mode(&self) -> u32 {
+        SleepMode::DeepSleep as u32
+    }
+}
+
+/// NMI handler, the watchdog is the only NMI source in use.
+pub unsafe extern "C" fn nmi_handler() {
+    WATCHDOG.handle_nmi();
+}
@@ -0,0 +1,179 @@
+//! Tick arithmetic of the cc26x0 AON RTC
+//!
+//! The conversions between RTC time, milliseconds, microseconds and alarm ticks,
+//! and how alarms are compared across the wrap of the 32-bit alarm counter. This
+//! has no dependencies, so unlike the chip crate it builds and its tests run on
+//! the host:
+//!
+//! ```text
+//! cd chips/rtc_ticks && cargo test
+//! ```
+
+#![no_std]
+
+/// Rate of the alarm counter, exact: one tick is 1/65536s (15.2587890625us).
+pub const TICKS_PER_SECOND: u32 = 1 << 16;
+
+/// The closest an alarm can be set to the current time, two LF clock periods.
+pub const MIN_TICKS: u32 = 4;
+
+/// Alarms up to this far behind the current time are considered due, anything
+/// further away is in the future after the counter wraps.
+pub const PAST_WINDOW: u32 = TICKS_PER_SECOND;
+
+/// The alarm counter value at RTC time `sec`, `subsec`.
+///
+/// The conversion is a plain truncation of the 64-bit RTC value, so no error
+/// accumulates: after `n` seconds the counter has advanced by exactly `n * 65536`
+/// ticks (modulo 2^32), and the accuracy of alarms over hours and days is the
+/// accuracy of the LF clock alone.
+pub fn ticks_from_time(sec: u32, subsec: u32) -> u32 {
+    (sec << 16) | (subsec >> 16)
+}
+
+/// Number of ticks in `ms` milliseconds, rounded down. Exact for multiples of
+/// 125ms: `ms_to_ticks(125) == 8192`, `ms_to_ticks(3_600_000) == 3600 * 65536`.
+pub fn ms_to_ticks(ms: u32) -> u32 {
+    (((ms as u64) << 16) / 1000) as u32
+}
+
+/// Duration of `ticks` in microseconds, rounded down.
+pub fn ticks_to_us(ticks: u32) -> u64 {
+    (ticks as u64 * 1_000_000) >> 16
+}
+
+/// Number of ticks in `us` microseconds, rounded up.
+pub fn us_to_ticks(us: u32) -> u32 {
+    let scaled = (us as u64) << 16;
+    let ticks = scaled / 1_000_000;
+    if ticks * 1_000_000 == scaled {
+        ticks as u32
+    } else {
+        ticks as u32 + 1
+    }
+}
+
+/// Ticks from `now` until `deadline`, deadlines in the past window are due now.
+pub fn ticks_until(now: u32, deadline: u32) -> u32 {
+    if now.wrapping_sub(deadline) <= PAST_WINDOW {
+        0
+    } else {
+        deadline.wrapping_sub(now)
+    }
+}
+
+/// The compare value for an alarm at `tics`, alarms that are due or closer than
+/// `MIN_TICKS` fire `MIN_TICKS` from `now`.
+///
+/// Everything is compared modulo 2^32, which keeps alarms across the wrap intact.
+pub fn compare_value(now: u32, tics: u32) -> u32 {
+    if tics.wrapping_sub(now) < MIN_TICKS || now.wrapping_sub(tics) <= PAST_WINDOW {
+        now.wrapping_add(MIN_TICKS)
+    } else {
+        tics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u32 = 3600;
+    const DAY: u32 = 24 * HOUR;
+
+    #[test]
+    fn ticks_from_time_spans() {
+        // Fractions of a second are kept to the tick
+        assert_eq!(ticks_from_time(0, 0x8000_0000), 0x8000);
+        assert_eq!(ticks_from_time(1, 0xFFFF_FFFF), 0x1_FFFF);
+
+        // Spans are exact in ticks, also once they no longer fit into the counter
+        for &(sec, subsec) in [(0, 0), (12345, 0x1234_5678), (0xFFFF, 0xFFFF_0000)].iter() {
+            let start = ticks_from_time(sec, subsec);
+            for &span in [HOUR, DAY, 3 * DAY, 7 * DAY].iter() {
+                let end = ticks_from_time(sec.wrapping_add(span), subsec);
+                assert_eq!(
+                    end.wrapping_sub(start),
+                    (span as u64 * TICKS_PER_SECOND as u64) as u32
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ms_to_ticks_spans() {
+        assert_eq!(ms_to_ticks(125), 8192);
+        assert_eq!(ms_to_ticks(1), 65);
+        assert_eq!(ms_to_ticks(HOUR * 1000), HOUR * TICKS_PER_SECOND);
+        // Longer spans wrap like the counter does
+        assert_eq!(
+            ms_to_ticks(DAY * 1000),
+            ticks_from_time(DAY, 0).wrapping_sub(ticks_from_time(0, 0))
+        );
+        assert_eq!(
+            ms_to_ticks(7 * DAY * 1000),
+            ticks_from_time(7 * DAY, 0).wrapping_sub(ticks_from_time(0, 0))
+        );
+    }
+
+    #[test]
+    fn ticks_to_us_spans() {
+        assert_eq!(ticks_to_us(1), 15);
+        assert_eq!(ticks_to_us(8192), 125_000);
+        assert_eq!(ticks_to_us(HOUR * TICKS_PER_SECOND), HOUR as u64 * 1_000_000);
+        // The whole counter range, a bit over 18 hours
+        assert_eq!(ticks_to_us(0xFFFF_FFFF), 65_535_999_984);
+    }
+
+    #[test]
+    fn us_to_ticks_spans() {
+        assert_eq!(us_to_ticks(0), 0);
+        assert_eq!(us_to_ticks(1), 1);
+        assert_eq!(us_to_ticks(15), 1);
+        assert_eq!(us_to_ticks(16), 2);
+        assert_eq!(us_to_ticks(125_000), 8192);
+        assert_eq!(us_to_ticks(HOUR * 1_000_000), HOUR * TICKS_PER_SECOND);
+        for &ticks in [1, 8192, 12345, HOUR * TICKS_PER_SECOND].iter() {
+            assert_eq!(us_to_ticks(ticks_to_us(ticks) as u32), ticks);
+        }
+    }
+
+    #[test]
+    fn alarm_across_wrap() {
+        let now = 0xFFFF_FF00;
+        assert_eq!(compare_value(now, 0x100), 0x100);
+        assert_eq!(ticks_until(now, 0x100), 0x200);
+
+        // An hour ahead, past the wrap
+        let tics = now.wrapping_add(HOUR * TICKS_PER_SECOND);
+        assert_eq!(compare_value(now, tics), tics);
+        assert_eq!(ticks_until(now, tics), HOUR * TICKS_PER_SECOND);
+
+        // Closer than MIN_TICKS, with the wrap in between
+        let now = 0xFFFF_FFFE;
+        assert_eq!(compare_value(now, 1), now.wrapping_add(MIN_TICKS));
+        assert_eq!(compare_value(now, 2), 2);
+    }
+
+    #[test]
+    fn alarm_past_window() {
+        for &now in [PAST_WINDOW, 0x1234_5678, 10, 0xFFFF_FFF0].iter() {
+            // Due: at or behind the current time, up to PAST_WINDOW
+            for &behind in [0, 1, PAST_WINDOW - 1, PAST_WINDOW].iter() {
+                let tics = now.wrapping_sub(behind);
+                assert_eq!(ticks_until(now, tics), 0);
+                assert_eq!(compare_value(now, tics), now.wrapping_add(MIN_TICKS));
+            }
+
+            // One tick further back is the far future, after the counter wraps
+            let tics = now.wrapping_sub(PAST_WINDOW + 1);
+            assert_eq!(ticks_until(now, tics), 0u32.wrapping_sub(PAST_WINDOW + 1));
+            assert_eq!(compare_value(now, tics), tics);
+
+            // The closest alarm in the future
+            let tics = now.wrapping_add(MIN_TICKS);
+            assert_eq!(ticks_until(now, tics), MIN_TICKS);
+            assert_eq!(compare_value(now, tics), tics);
+        }
+    }
+}